@@ -0,0 +1,137 @@
+use crate::util::escape_html;
+use pulldown_cmark::{Event, Tag};
+
+// Admonitions (callouts) are written either GitHub style, as a blockquote
+// starting with a marker:
+//   > [!WARNING]
+//   > Mind the gap.
+// or as a fenced container, with an optional title:
+//   :::tip Quick start
+//   Run `webgenr`.
+//   :::
+// Containers are handled as the `admonition` shortcode (see `shortcode.rs`),
+// so a `shortcodes/admonition.hbs` template can replace the markup below.
+
+pub const ADMONITION_SHORTCODE: &str = "admonition";
+
+// kinds recognized in `> [!KIND]` markers
+const ALERT_KINDS: [&str; 5] = ["note", "tip", "important", "warning", "caution"];
+
+// default title for a kind: "warning" -> "Warning"
+fn default_title(kind: &str) -> String {
+    let mut chars = kind.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+// opening markup; EPUB chapters use a `div`, which all reading systems
+// show inline, where some treat `aside` as a popup note
+pub fn open_html(kind: &str, title: Option<&str>, epub: bool) -> String {
+    let kind = kind.to_lowercase();
+    let title = title.map(|title| title.to_string()).unwrap_or_else(|| default_title(&kind));
+    let element = if epub { "div" } else { "aside" };
+    format!(
+        "<{} class=\"admonition {}\" role=\"note\">\n<p class=\"admonition-title\">{}</p>\n",
+        element,
+        escape_html(&kind),
+        escape_html(&title)
+    )
+}
+
+pub fn close_html(epub: bool) -> &'static str {
+    if epub {
+        "</div>\n"
+    } else {
+        "</aside>\n"
+    }
+}
+
+// kind and title from the first line of a blockquote: "[!NOTE] Optional title"
+fn parse_marker(line: &str) -> Option<(String, Option<String>)> {
+    let rest = line.trim_start().strip_prefix("[!")?;
+    let (kind, title) = rest.split_once(']')?;
+    let kind = kind.to_lowercase();
+    if !ALERT_KINDS.contains(&kind.as_str()) {
+        return None;
+    }
+    let title = Some(title.trim()).filter(|title| !title.is_empty());
+    Some((kind, title.map(|title| title.to_string())))
+}
+
+// replace blockquotes starting with a `[!KIND]` marker by admonitions
+pub fn replace_alerts<'a>(events: Vec<Event<'a>>, epub: bool) -> Vec<Event<'a>> {
+    let mut result = Vec::with_capacity(events.len());
+    // for each open blockquote, whether it became an admonition
+    let mut open_quotes: Vec<bool> = Vec::new();
+    let mut i = 0;
+    while i < events.len() {
+        match &events[i] {
+            Event::Start(Tag::BlockQuote) => {
+                let mut is_alert = false;
+                if let Some(Event::Start(Tag::Paragraph)) = events.get(i + 1) {
+                    // the marker may be split over several text events
+                    let mut line = String::new();
+                    let mut j = i + 2;
+                    while let Some(Event::Text(text)) = events.get(j) {
+                        line.push_str(text);
+                        j += 1;
+                    }
+                    if let Some((kind, title)) = parse_marker(&line) {
+                        is_alert = true;
+                        result.push(Event::Html(open_html(&kind, title.as_deref(), epub).into()));
+                        i = match events.get(j) {
+                            // marker on a line of its own: drop the paragraph
+                            Some(Event::End(Tag::Paragraph)) => j + 1,
+                            Some(Event::SoftBreak) | Some(Event::HardBreak) => {
+                                result.push(Event::Start(Tag::Paragraph));
+                                j + 1
+                            }
+                            _ => {
+                                result.push(Event::Start(Tag::Paragraph));
+                                j
+                            }
+                        };
+                    }
+                }
+                open_quotes.push(is_alert);
+                if is_alert {
+                    continue;
+                }
+            }
+            // every blockquote end pops its entry; ends of admonitions are replaced
+            Event::End(Tag::BlockQuote) if open_quotes.pop() == Some(true) => {
+                result.push(Event::Html(close_html(epub).into()));
+                i += 1;
+                continue;
+            }
+            _ => {}
+        }
+        result.push(events[i].clone());
+        i += 1;
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pulldown_cmark::{html, Parser};
+
+    #[test]
+    fn test_replace_alerts() {
+        let markdown = "> [!WARNING]\n> Mind *the* gap.\n\n> [!tip] Quick start\n> Run it.\n\n> [!OTHER]\n> plain quote\n";
+        let events = replace_alerts(Parser::new(markdown).collect(), false);
+        let mut out = String::new();
+        html::push_html(&mut out, events.into_iter());
+        assert_eq!(
+            out,
+            "<aside class=\"admonition warning\" role=\"note\">\n<p class=\"admonition-title\">Warning</p>\n\
+             <p>Mind <em>the</em> gap.</p>\n</aside>\n\
+             <aside class=\"admonition tip\" role=\"note\">\n<p class=\"admonition-title\">Quick start</p>\n\
+             <p>Run it.</p>\n</aside>\n\
+             <blockquote>\n<p>[!OTHER]\nplain quote</p>\n</blockquote>\n"
+        );
+    }
+}
@@ -0,0 +1,185 @@
+use crate::highlight::HighlightMode;
+use anyhow::bail;
+use pulldown_cmark::Options;
+use std::path::PathBuf;
+
+// markdown syntax beyond CommonMark, enabled site-wide with
+// `--markdown-extensions` and per document in front matter
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MarkdownExtensions {
+    pub tables: bool,
+    pub footnotes: bool,
+    pub strikethrough: bool,
+    pub tasklists: bool,
+    pub smart_punctuation: bool,
+    pub heading_attributes: bool,
+    // `$...$` and `$$...$$` rendered as MathML (not a pulldown-cmark option)
+    pub math: bool,
+    // standalone images with a caption as numbered `<figure>`s (not a pulldown-cmark option)
+    pub figures: bool,
+}
+
+impl Default for MarkdownExtensions {
+    // only strikethrough, as before extensions were configurable
+    fn default() -> Self {
+        MarkdownExtensions {
+            tables: false,
+            footnotes: false,
+            strikethrough: true,
+            tasklists: false,
+            smart_punctuation: false,
+            heading_attributes: false,
+            math: false,
+            figures: false,
+        }
+    }
+}
+
+impl MarkdownExtensions {
+    pub const NAMES: [&'static str; 8] = [
+        "tables",
+        "footnotes",
+        "strikethrough",
+        "tasklists",
+        "smart-punctuation",
+        "heading-attributes",
+        "math",
+        "figures",
+    ];
+
+    // exactly the named extensions, like ["tables", "footnotes"]
+    pub fn from_names<S: AsRef<str>>(names: &[S]) -> anyhow::Result<Self> {
+        let mut extensions = MarkdownExtensions {
+            strikethrough: false,
+            ..Default::default()
+        };
+        for name in names {
+            extensions.set(name.as_ref(), true)?;
+        }
+        Ok(extensions)
+    }
+
+    // names may use `-` or `_`, like "smart-punctuation" or "smart_punctuation"
+    pub fn set(&mut self, name: &str, enabled: bool) -> anyhow::Result<()> {
+        let flag = match name.trim().replace('_', "-").as_str() {
+            "tables" => &mut self.tables,
+            "footnotes" => &mut self.footnotes,
+            "strikethrough" => &mut self.strikethrough,
+            "tasklists" => &mut self.tasklists,
+            "smart-punctuation" => &mut self.smart_punctuation,
+            "heading-attributes" => &mut self.heading_attributes,
+            "math" => &mut self.math,
+            "figures" => &mut self.figures,
+            _ => bail!(
+                "unknown markdown extension '{}', expected one of: {}",
+                name,
+                Self::NAMES.join(", ")
+            ),
+        };
+        *flag = enabled;
+        Ok(())
+    }
+
+    pub fn options(&self) -> Options {
+        let mut options = Options::empty();
+        for (enabled, option) in [
+            (self.tables, Options::ENABLE_TABLES),
+            (self.footnotes, Options::ENABLE_FOOTNOTES),
+            (self.strikethrough, Options::ENABLE_STRIKETHROUGH),
+            (self.tasklists, Options::ENABLE_TASKLISTS),
+            (self.smart_punctuation, Options::ENABLE_SMART_PUNCTUATION),
+            (self.heading_attributes, Options::ENABLE_HEADING_ATTRIBUTES),
+        ] {
+            if enabled {
+                options.insert(option);
+            }
+        }
+        options
+    }
+}
+
+// site generation options, typically set from command-line flags
+#[derive(Debug, Default, Clone)]
+pub struct Config {
+    // also write `search_inverted.json`, a pre-tokenized index so
+    // client-side search doesn't need to build one in the browser
+    pub search_inverted_index: bool,
+    // publish documents with `draft: true` in front matter
+    pub include_drafts: bool,
+    // publish documents with a front matter `date:` that is in the future
+    pub include_future: bool,
+    // url pattern for markdown pages, like "/:year/:month/:slug/"
+    pub permalink: Option<String>,
+    // fail instead of warning when internal links, images or audio are missing
+    pub strict_links: bool,
+    // add a "¶" link to each heading's id
+    pub heading_anchors: bool,
+    pub markdown_extensions: MarkdownExtensions,
+    // syntax highlighting of code blocks, and the syntect theme to use
+    // (None uses `highlight::DEFAULT_THEME`)
+    pub highlight_mode: HighlightMode,
+    pub highlight_theme: Option<String>,
+    // extra widths to resize images to, and whether to also write WebP
+    // versions; markdown images then get a `srcset` (see `images.rs`)
+    pub image_widths: Vec<u32>,
+    pub image_webp: bool,
+    // `sizes` attribute for images with a `srcset` (None uses "100vw")
+    pub image_sizes: Option<String>,
+    // where resized images are cached between builds (None uses
+    // `.webgenr-cache` in the source directory)
+    pub cache_dir: Option<PathBuf>,
+    // remove EXIF/XMP metadata (like GPS position) from jpeg, png and webp
+    // images copied to the site or book, applying their orientation first
+    pub strip_metadata: bool,
+    // show the captions of audio and video as a transcript below the player
+    pub transcripts: bool,
+    // url of a FontAwesome kit script for `default.hbs` to load; the
+    // built-in templates only use the embedded icons (see `icons.rs`)
+    pub fontawesome_kit: Option<String>,
+    // render templates without html-escaping variables, as before escaping
+    // became the default, for templates that output `{{ body }}`
+    pub unescaped_templates: bool,
+    // filter raw html in markdown against an allowlist, and unsafe link
+    // urls like `javascript:` (see `sanitize.rs`)
+    pub sanitize: bool,
+    // carry on past files that fail, and report all their errors at the end
+    pub keep_going: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_markdown_extension_names() {
+        let all = MarkdownExtensions::from_names(&MarkdownExtensions::NAMES[..]).expect("all names");
+        assert_eq!(
+            all,
+            MarkdownExtensions {
+                tables: true,
+                footnotes: true,
+                strikethrough: true,
+                tasklists: true,
+                smart_punctuation: true,
+                heading_attributes: true,
+                math: true,
+                figures: true,
+            }
+        );
+        let some = MarkdownExtensions::from_names(&["tables", " smart_punctuation"]).expect("names");
+        assert!(some.tables && some.smart_punctuation);
+        assert!(!some.strikethrough && !some.footnotes);
+        assert_eq!(some.options(), Options::ENABLE_TABLES | Options::ENABLE_SMART_PUNCTUATION);
+
+        let e = MarkdownExtensions::from_names(&["tables", "emoji"]).expect_err("unknown name");
+        assert_eq!(
+            e.to_string(),
+            "unknown markdown extension 'emoji', expected one of: tables, footnotes, strikethrough, \
+             tasklists, smart-punctuation, heading-attributes, math, figures"
+        );
+
+        let default = MarkdownExtensions::default();
+        assert_eq!(default, MarkdownExtensions::from_names(&["strikethrough"]).expect("strikethrough"));
+        assert_eq!(default.options(), Options::ENABLE_STRIKETHROUGH);
+    }
+}
@@ -1,26 +1,48 @@
-use crate::Web;
-use crate::util::{is_audio_file, get_ext, get_mimetype};
-use pulldown_cmark::{Event, Parser as MarkdownParser, Tag};
+use crate::{Config, MarkdownExtensions, Web};
+use crate::admonition::{self, ADMONITION_SHORTCODE};
+use crate::figure;
+use crate::gallery::{self, GalleryImage, GALLERY_SHORTCODE};
+use crate::images::{self, ImageEmbed, LocalImage, IMAGE_PARTIAL};
+use crate::media::{self, AudioMetadata, MediaLink, VideoExtras, AUDIO_PARTIAL, VIDEO_PARTIAL};
+use crate::metadata;
+use crate::playlist::{self, Track};
+use crate::preprocess::Preprocessed;
+use crate::sanitize::{self, Sanitizer};
+use crate::shortcode::{Shortcode, SHORTCODE_TEMPLATE_DIR};
+use crate::highlight::{CodeInfo, HighlightMode, HIGHLIGHT_CSS_FILE};
+use crate::redirect::Redirect;
+use crate::search::{PlainText, SearchEntry};
+use crate::toc::{self, TocEntry};
+use crate::util::{
+    escape_html, get_ext, get_mimetype, is_audio_file, is_external_url, is_video_file, normalize_path,
+    percent_decode, relative_url, slugify,
+};
+use pulldown_cmark::{CodeBlockKind, CowStr, Event, Parser as MarkdownParser, Tag};
 use serde_json;
 use serde_yaml;
-use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::io::{Read, Write};
+use std::ops::Range;
 use std::path::{Path, PathBuf};
-use anyhow::bail;
+use anyhow::{anyhow, bail, Context};
+use chrono::{Local, NaiveDate, NaiveDateTime, DateTime};
 
 pub struct FrontMatter {
-    vars: std::collections::HashMap<String, String>,
+    vars: std::collections::HashMap<String, serde_json::Value>,
+    // line in the file of each top-level key, for messages
+    key_lines: HashMap<String, usize>,
 }
 
 impl FrontMatter {
-    // currently only supports string data types, like
+    // yaml front matter at the start of a markdown file, like
     // ---
     // title: "My Website"
+    // draft: true
     // ---
     // input: if it has yaml front matter, the yaml is removeed
     // returns: yaml front matter as struct or None
-    pub fn split_yml_from_string<'a>(input: &mut String) -> anyhow::Result<Option<FrontMatter>> {
+    pub fn split_yml_from_string<'a>(input: &mut String) -> Result<Option<FrontMatter>, serde_yaml::Error> {
         let mut yaml: bool = false;
         let mut yaml_separator: &str = "";
         let mut yaml_text: Option<String> = None;
@@ -44,45 +66,177 @@ impl FrontMatter {
                 // println!("========== text ===========");
                 // println!("{}", &input);
                 // println!("===========================");
+                // the yaml starts on the second line, after `---`
+                let key_lines = yaml_string
+                    .lines()
+                    .enumerate()
+                    .filter(|(_, line)| !line.starts_with([' ', '\t', '#', '-']))
+                    .filter_map(|(i, line)| Some((line.split_once(':')?.0.trim().to_string(), i + 2)))
+                    .collect();
                 return Ok(Some(FrontMatter {
-                    vars: serde_yaml::from_str(&yaml_string)?, // TODO: support more complex data types?
+                    vars: serde_yaml::from_str(&yaml_string)?,
+                    key_lines,
                 }));
             }
         }
         Ok(None)
     }
+
+    pub fn get_str(&self, key: &str) -> Option<&str> {
+        self.vars.get(key).and_then(|value| value.as_str())
+    }
+
+    pub fn line(&self, key: &str) -> Option<usize> {
+        self.key_lines.get(key).copied()
+    }
+
+    pub fn get_bool(&self, key: &str) -> Option<bool> {
+        self.vars.get(key).and_then(|value| value.as_bool())
+    }
+
+    // `date:` accepts RFC 3339 (2022-10-01T09:00:00-07:00),
+    // local date and time (2022-10-01 09:00:00) or just a date (2022-10-01)
+    pub fn date(&self) -> anyhow::Result<Option<NaiveDateTime>> {
+        let date_str = match self.get_str("date") {
+            Some(date_str) => date_str.trim(),
+            None => return Ok(None),
+        };
+        if let Ok(date_time) = DateTime::parse_from_rfc3339(date_str) {
+            return Ok(Some(date_time.with_timezone(&Local).naive_local()));
+        }
+        if let Ok(date_time) = NaiveDateTime::parse_from_str(date_str, "%Y-%m-%d %H:%M:%S") {
+            return Ok(Some(date_time));
+        }
+        match NaiveDate::parse_from_str(date_str, "%Y-%m-%d") {
+            Ok(date) => Ok(date.and_hms_opt(0, 0, 0)),
+            Err(e) => bail!("unrecognized date '{}': {}", date_str, e),
+        }
+    }
+}
+
+// details gathered while rendering markdown, for use beyond the page itself
+#[derive(Debug, Default)]
+pub struct RenderInfo {
+    pub text: PlainText,
+    // internal links, images and audio that don't match any source file
+    pub broken_links: Vec<String>,
+    // other problems worth reporting, like math that could not be rendered
+    pub warnings: Vec<String>,
+    pub toc: Vec<TocEntry>,
 }
 
 pub enum DocumentInfo {
     Markdown {
         front_matter: Option<FrontMatter>,
         text: String,
+        // number of lines removed with the front matter, so that
+        // line numbers in messages match the source file
+        front_matter_lines: usize,
     },
     Other,
 }
 pub struct Document {
     pub source_path: PathBuf,
     pub info: DocumentInfo,
+    // generated file, relative to the output directory
+    pub rel_outpath: PathBuf,
+    // site-relative url used to link to the generated file, with `/` separators
+    // and no leading `/`, for example "notes/one.html" or "2022/10/hello/"
+    pub url: String,
+}
+
+// a yaml error in front matter, pointing at its line in the file (the yaml
+// starts on the second line, after `---`)
+fn front_matter_error(path: &Path, e: serde_yaml::Error) -> anyhow::Error {
+    let message = e.to_string();
+    match e.location() {
+        Some(location) => {
+            // the message ends with the location within the yaml
+            let message = message.split(" at line ").next().unwrap_or(&message);
+            anyhow!(
+                "{}:{}:{}: invalid front matter: {}",
+                path.display(),
+                location.line() + 1,
+                location.column(),
+                message
+            )
+        }
+        None => anyhow!("{}: invalid front matter: {}", path.display(), message),
+    }
+}
+
+// convert a relative path into url segments joined by `/`
+fn path_to_url(path: &Path) -> String {
+    path.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+// output file (relative to the output directory) and url for a site path,
+// which is written as `index.html` in a directory unless it ends in `.html`;
+// fails for a path with `..`, which could leave the output directory
+fn site_path_to_outpath(site_path: &str) -> anyhow::Result<(PathBuf, String)> {
+    let segments: Vec<&str> = site_path
+        .split(['/', '\\'])
+        .filter(|s| !s.is_empty() && *s != ".")
+        .collect();
+    if segments.contains(&"..") {
+        bail!("'{}' is outside the site ('..' is not allowed)", site_path);
+    }
+    let is_file = matches!(segments.last(), Some(last) if last.ends_with(".html") || last.ends_with(".htm"));
+    let rel_outpath: PathBuf = if is_file {
+        segments.iter().collect()
+    } else {
+        segments.iter().chain(std::iter::once(&"index.html")).collect()
+    };
+    let url = if is_file || segments.is_empty() {
+        segments.join("/")
+    } else {
+        format!("{}/", segments.join("/"))
+    };
+    Ok((rel_outpath, url))
 }
 
 impl Document {
-    pub fn new<P: AsRef<Path>>(source_path: P) -> anyhow::Result<Self> {
-        let info = if Self::is_markdown_path(&source_path) {
+    // `root` is the source directory, used to determine the output path
+    pub fn new<P: AsRef<Path>>(source_path: P, root: &Path, config: &Config) -> anyhow::Result<Self> {
+        let markdown = if Self::is_markdown_path(&source_path) {
             let mut f = fs::File::open(&source_path)?;
             let mut markdown = String::new();
             f.read_to_string(&mut markdown)?;
-            let front_matter = FrontMatter::split_yml_from_string(&mut markdown)?;
-            DocumentInfo::Markdown {
-                front_matter,
-                text: markdown,
-            }
+            Some(markdown)
         } else {
-            DocumentInfo::Other
+            None
         };
-        Ok(Document {
-            source_path: source_path.as_ref().to_path_buf(),
+        Self::from_text(source_path.as_ref(), markdown, root, config)
+    }
+
+    // a document with the text of its markdown file, or None for other files
+    fn from_text(source_path: &Path, markdown: Option<String>, root: &Path, config: &Config) -> anyhow::Result<Self> {
+        let info = match markdown {
+            Some(mut markdown) => {
+                let line_count = markdown.lines().count();
+                let front_matter = FrontMatter::split_yml_from_string(&mut markdown)
+                    .map_err(|e| front_matter_error(source_path, e))?;
+                DocumentInfo::Markdown {
+                    front_matter,
+                    front_matter_lines: line_count - markdown.lines().count(),
+                    text: markdown,
+                }
+            }
+            None => DocumentInfo::Other,
+        };
+        let mut doc = Document {
+            source_path: source_path.to_path_buf(),
             info,
-        })
+            rel_outpath: PathBuf::new(),
+            url: String::new(),
+        };
+        let (rel_outpath, url) = doc.permalink(root, config)?;
+        doc.rel_outpath = rel_outpath;
+        doc.url = url;
+        Ok(doc)
     }
     // The only reason this would fail is if at some point we create a
     // Document with path from user input.
@@ -99,16 +253,281 @@ impl Document {
         }
     }
 
-    fn outpath(&self, root: &PathBuf, out_dir: &PathBuf) -> std::io::Result<PathBuf> {
+    pub fn front_matter(&self) -> Option<&FrontMatter> {
+        match &self.info {
+            DocumentInfo::Markdown { front_matter, .. } => front_matter.as_ref(),
+            DocumentInfo::Other => None,
+        }
+    }
+
+    // reason why this document should be left out of the generated output
+    // (drafts and future dates), or None if it should be published
+    pub fn skip_reason(&self, config: &Config) -> anyhow::Result<Option<String>> {
+        let front_matter = match self.front_matter() {
+            Some(front_matter) => front_matter,
+            None => return Ok(None),
+        };
+        if !config.include_drafts && front_matter.get_bool("draft") == Some(true) {
+            return Ok(Some("draft (use --drafts to include)".into()));
+        }
+        let date = front_matter
+            .date()
+            .with_context(|| format!("invalid front matter in {}", self.source_path.display()))?;
+        if let Some(date) = date {
+            if !config.include_future && date > Local::now().naive_local() {
+                return Ok(Some(format!("dated in the future, {} (use --future to include)", date)));
+            }
+        }
+        Ok(None)
+    }
+
+    // output path (relative to the output directory) and url of this document.
+    // By default the source path is mirrored, with markdown files becoming `.html`.
+    // For markdown, a front matter `permalink` (like "/about/") or `slug`
+    // takes precedence over the site-wide `config.permalink` pattern.
+    // A url ending in `/` is written as `index.html` in that directory.
+    fn permalink(&self, root: &Path, config: &Config) -> anyhow::Result<(PathBuf, String)> {
         let rel_path = self
             .source_path
-            .strip_prefix(&root)
+            .strip_prefix(root)
             .expect("strip prefix match");
-        Ok(out_dir.join(rel_path))
+        if !self.is_markdown() {
+            return Ok((rel_path.to_path_buf(), path_to_url(rel_path)));
+        }
+        let default_path = rel_path.with_extension("html");
+        let default = (default_path.clone(), path_to_url(&default_path));
+
+        let front_matter = self.front_matter();
+        let permalink = front_matter.and_then(|fm| fm.get_str("permalink"));
+        let slug = front_matter.and_then(|fm| fm.get_str("slug"));
+        // a slug fills `:slug` in the site's pattern, or else replaces the file name
+        const SLUG_PATTERN: &str = ":path/:slug/";
+        let pattern = match (permalink, slug, &config.permalink) {
+            (Some(permalink), _, _) => permalink,
+            (None, Some(_), Some(pattern)) => pattern.as_str(),
+            (None, Some(_), None) => SLUG_PATTERN,
+            // index pages stay where they are so directories keep their landing page
+            (None, None, Some(pattern)) if self.file_stem()? != "index" => pattern.as_str(),
+            _ => return Ok(default),
+        };
+
+        let url = match self.expand_permalink(pattern, rel_path)? {
+            Some(url) => url,
+            None if permalink.is_some() => bail!(
+                "permalink '{}' needs a front matter date in {}",
+                pattern,
+                self.source_path.display()
+            ),
+            None => {
+                info!(
+                    "no date for permalink '{}', using default path for {}",
+                    pattern,
+                    self.source_path.display()
+                );
+                match slug {
+                    Some(_) => self.expand_permalink(SLUG_PATTERN, rel_path)?.expect("no date needed"),
+                    None => return Ok(default),
+                }
+            }
+        };
+        let key = match (permalink, slug) {
+            (Some(_), _) => "permalink",
+            (None, Some(_)) => "slug",
+            (None, None) => "",
+        };
+        site_path_to_outpath(&url).map_err(|e| anyhow!("{}: invalid permalink: {}", self.location(key), e))
     }
 
-    pub fn webgen(&self, context: &Web) -> anyhow::Result<()> {
-        let outpath = self.outpath(&context.in_path, &context.out_path)?;
+    // the file, with the line of a front matter key when there is one, like
+    // "notes/post.md:3", for messages
+    fn location(&self, key: &str) -> String {
+        match self.front_matter().and_then(|fm| fm.line(key)) {
+            Some(line) => format!("{}:{}", self.source_path.display(), line),
+            None => self.source_path.display().to_string(),
+        }
+    }
+
+    // redirect pages for the front matter `aliases` list, like
+    // aliases: [/old/path.html, old-name.html]
+    // aliases starting with `/` are relative to the top of the site,
+    // others to the directory of this document's source file
+    pub fn aliases(&self, root: &Path) -> anyhow::Result<Vec<Redirect>> {
+        let value = match self.front_matter().and_then(|fm| fm.vars.get("aliases")) {
+            Some(value) => value,
+            None => return Ok(Vec::new()),
+        };
+        let aliases: Vec<&str> = match value {
+            serde_json::Value::String(alias) => vec![alias.as_str()],
+            serde_json::Value::Array(list) => list
+                .iter()
+                .map(|alias| alias.as_str())
+                .collect::<Option<_>>()
+                .with_context(|| format!("aliases must be strings in {}", self.source_path.display()))?,
+            _ => bail!("aliases must be a list in {}", self.source_path.display()),
+        };
+        let source_dir = self
+            .source_path
+            .strip_prefix(root)
+            .expect("strip prefix match")
+            .parent()
+            .map(path_to_url)
+            .unwrap_or_default();
+        let mut redirects = Vec::new();
+        for alias in aliases {
+            let site_path = match alias.strip_prefix('/') {
+                Some(site_path) => site_path.to_string(),
+                None => format!("{}/{}", source_dir, alias),
+            };
+            let (rel_outpath, url) = site_path_to_outpath(&site_path)
+                .map_err(|e| anyhow!("{}: invalid alias: {}", self.location("aliases"), e))?;
+            redirects.push(Redirect {
+                source_path: self.source_path.clone(),
+                rel_outpath,
+                url,
+                target_url: self.url.clone(),
+            });
+        }
+        Ok(redirects)
+    }
+
+    // substitute :year, :month, :day, :slug and :path into a permalink pattern,
+    // returns None if the pattern needs a date and the document has none
+    fn expand_permalink(&self, pattern: &str, rel_path: &Path) -> anyhow::Result<Option<String>> {
+        let front_matter = self.front_matter();
+        let mut url = pattern.to_string();
+        if url.contains(":year") || url.contains(":month") || url.contains(":day") {
+            let date = match front_matter {
+                Some(front_matter) => front_matter
+                    .date()
+                    .with_context(|| format!("invalid front matter in {}", self.source_path.display()))?,
+                None => None,
+            };
+            let date = match date {
+                Some(date) => date,
+                None => return Ok(None),
+            };
+            url = url
+                .replace(":year", &date.format("%Y").to_string())
+                .replace(":month", &date.format("%m").to_string())
+                .replace(":day", &date.format("%d").to_string());
+        }
+        let slug = match front_matter.and_then(|fm| fm.get_str("slug")) {
+            Some(slug) => slug.trim_matches('/').to_string(),
+            None => slugify(self.file_stem()?),
+        };
+        let dir = rel_path.parent().map(path_to_url).unwrap_or_default();
+        Ok(Some(url.replace(":slug", &slug).replace(":path", &dir)))
+    }
+
+    // site-wide markdown extensions, adjusted by front matter like
+    // markdown_extensions:
+    //   tables: true
+    //   smart_punctuation: false
+    fn markdown_extensions(&self, config: &Config) -> anyhow::Result<MarkdownExtensions> {
+        let mut extensions = config.markdown_extensions;
+        let value = match self.front_matter().and_then(|fm| fm.vars.get("markdown_extensions")) {
+            Some(value) => value,
+            None => return Ok(extensions),
+        };
+        let map = match value.as_object() {
+            Some(map) => map,
+            None => bail!(
+                "markdown_extensions should map extension names to true or false in {}",
+                self.source_path.display()
+            ),
+        };
+        for (name, enabled) in map {
+            let enabled = match enabled.as_bool() {
+                Some(enabled) => enabled,
+                None => bail!(
+                    "markdown_extensions: '{}' should be true or false in {}",
+                    name,
+                    self.source_path.display()
+                ),
+            };
+            extensions
+                .set(name, enabled)
+                .with_context(|| format!("invalid front matter in {}", self.source_path.display()))?;
+        }
+        Ok(extensions)
+    }
+
+    // line number in the source file for a byte offset into the markdown text
+    fn line_number(&self, offset: usize) -> usize {
+        match &self.info {
+            DocumentInfo::Markdown { text, front_matter_lines, .. } => {
+                text[..offset].matches('\n').count() + front_matter_lines + 1
+            }
+            DocumentInfo::Other => 0,
+        }
+    }
+
+    // resolve the path part of a local link found in this document's
+    // markdown to the document it points at, if that is part of the site
+    fn resolve_link<'a>(&self, link_path: &str, context: &'a Web) -> Option<&'a Document> {
+        self.link_targets(link_path, context).iter().find_map(|target| context.find_doc(target))
+    }
+
+    // the normalized source paths a local link can point at: the file, or
+    // the index pages of a directory
+    fn link_targets(&self, link_path: &str, context: &Web) -> Vec<PathBuf> {
+        let link_path = percent_decode(link_path);
+        let target = match link_path.strip_prefix('/') {
+            Some(site_path) => context.in_path.join(site_path),
+            None => match self.source_path.parent() {
+                Some(dir) => dir.join(link_path.as_ref()),
+                None => return Vec::new(),
+            },
+        };
+        if link_path.ends_with('/') {
+            // a directory link goes to its index page
+            return ["index.md", "index.markdown", "index.html"]
+                .iter()
+                .map(|index| normalize_path(&target.join(index)))
+                .collect();
+        }
+        vec![normalize_path(&target)]
+    }
+
+    // rewrite a link, image or audio url from the markdown source so it points
+    // at the generated output, keeping any `?query` or `#fragment`.
+    // External urls and in-page anchors are returned unchanged; local urls that
+    // don't match a source file are recorded in `broken_links` and left as written
+    fn rewrite_url<'a>(
+        &self,
+        url: CowStr<'a>,
+        offset: usize,
+        context: &Web,
+        broken_links: &mut Vec<String>,
+    ) -> CowStr<'a> {
+        if url.is_empty() || url.starts_with('#') || is_external_url(&url) {
+            return url;
+        }
+        let split_at = url.find(['?', '#']).unwrap_or(url.len());
+        let (link_path, suffix) = url.split_at(split_at);
+        match self.resolve_link(link_path, context) {
+            Some(doc) => format!("{}{}", relative_url(&self.url, &doc.url), suffix).into(),
+            None => {
+                let skipped = self
+                    .link_targets(link_path, context)
+                    .iter()
+                    .find_map(|target| context.skip_reason(target));
+                let problem = match skipped {
+                    Some(reason) => format!("link to a page that isn't published: {}, {}", url, reason),
+                    None if link_path.ends_with('/') => {
+                        format!("link target not found: {} (no index.md in that folder)", url)
+                    }
+                    None => format!("link target not found: {}", url),
+                };
+                broken_links.push(format!("{}:{}: {}", self.source_path.display(), self.line_number(offset), problem));
+                url
+            }
+        }
+    }
+
+    // returns search index entry for markdown documents
+    pub fn webgen(&self, context: &Web) -> anyhow::Result<Option<SearchEntry>> {
+        let outpath = context.out_path.join(&self.rel_outpath);
         match &self.info {
             DocumentInfo::Other => {
                 // copy file
@@ -117,43 +536,319 @@ impl Document {
                     self.source_path.display(),
                     &outpath.display()
                 );
-                std::fs::copy(&self.source_path, outpath)?;
+                if self.strips_metadata(context) {
+                    fs::write(outpath, self.stripped_image()?)?;
+                } else {
+                    std::fs::copy(&self.source_path, outpath)?;
+                }
+                if let Some((rel_outpath, _, vtt)) = self.converted_captions(context)? {
+                    fs::write(context.out_path.join(rel_outpath), vtt)?;
+                }
+                Ok(None)
             }
-            DocumentInfo::Markdown { front_matter, text } => {
+            DocumentInfo::Markdown { front_matter, text, .. } => {
                 let out_file = fs::OpenOptions::new()
                     .write(true)
                     .create(true)
-                    .open(outpath.with_extension("html"))?;
+                    .open(&outpath)?;
                 info!(
                     "convert-> {}\t{}",
                     self.source_path.display(),
-                    outpath.with_extension("html").display()
+                    outpath.display()
                 );
                 let mut writer = std::io::BufWriter::new(out_file);
 
                 // generate html
                 let mut html = Vec::new();
-                Self::write_html(&mut html, &text)?;
-                let html_string = String::from_utf8(html)?;
+                let render_info = self.write_html(&mut html, &text, 0, &mut HashSet::new(), context)?;
+                self.report(&render_info, context)?;
+                let mut html_string = String::from_utf8(html)?;
+                html_string.push_str(&self.front_matter_blocks(context)?);
 
                 // insert into handlebars template
                 let mut template_vars = match front_matter {
                     Some(front_matter) => front_matter.vars.clone(),
                     None => Default::default(),
                 };
-                if let Some(_) = template_vars.insert("body".into(), html_string) {
+                if let Some(_) = template_vars.insert("body".into(), html_string.into()) {
                     println!("warning: yaml var 'body' will be ignored");
                 }
+                // relative path back to the top of the site, for stylesheets and other links
+                let root = relative_url(&self.url, "");
+                if template_vars.insert("root".into(), root.into()).is_some() {
+                    println!("warning: yaml var 'root' will be ignored");
+                }
+                if context.config.highlight_mode == HighlightMode::Css {
+                    template_vars.insert("highlight_css".into(), HIGHLIGHT_CSS_FILE.into());
+                }
+                if let Some(kit) = &context.config.fontawesome_kit {
+                    template_vars.insert("fontawesome_kit".into(), kit.as_str().into());
+                }
+                let toc = serde_json::to_value(&render_info.toc)?;
+                if template_vars.insert("toc".into(), toc).is_some() {
+                    println!("warning: yaml var 'toc' will be ignored");
+                }
 
                 let s = context
                     .template_registry
                     .render("default", &serde_json::json!(template_vars))?;
 
                 writer.write_all(s.as_bytes())?;
+
+                let title = match template_vars.get("title").and_then(|title| title.as_str()) {
+                    Some(title) => title.to_string(),
+                    None => match render_info.text.headings.first() {
+                        Some(heading) => heading.clone(),
+                        None => self.file_stem()?.to_string(),
+                    },
+                };
+                Ok(Some(SearchEntry {
+                    title,
+                    url: self.url.clone(),
+                    headings: render_info.text.headings,
+                    body: render_info.text.body,
+                }))
+            }
+        }
+    }
+
+    // poster, size and captions for a video from files next to it
+    fn video_extras(&self, video: &Document, context: &Web) -> VideoExtras {
+        let sibling = |ext: &str| context.find_doc(&normalize_path(&video.source_path.with_extension(ext)));
+        let mut extras = VideoExtras::default();
+        if let Some(poster) = media::POSTER_EXTENSIONS.iter().find_map(|ext| sibling(ext)) {
+            extras.poster = Some(relative_url(&self.url, &poster.url));
+            if let Ok((width, height)) = images::dimensions(&poster.source_path) {
+                extras.width = Some(width);
+                extras.height = Some(height);
+            }
+        }
+        extras
+    }
+
+    // tags of a linked audio file, with any artwork written next to it in
+    // the output (but not for EPUB chapters, which can only use book files)
+    fn audio_metadata(&self, audio: &Document, context: &Web) -> anyhow::Result<AudioMetadata> {
+        let mut metadata = media::read_audio_metadata(&audio.source_path)
+            .with_context(|| format!("could not read metadata of {}", audio.url))?;
+        if let (Some((data, ext)), false) = (&metadata.artwork_data, context.epub) {
+            let stem = audio.source_path.file_stem().unwrap_or_default().to_string_lossy();
+            let file_name = format!("{}-artwork.{}", stem, ext);
+            let outpath = context.out_path.join(audio.rel_outpath.with_file_name(&file_name));
+            if let Some(parent) = outpath.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(&outpath, data).with_context(|| format!("could not write {}", outpath.display()))?;
+            let url = match audio.url.rfind('/') {
+                Some(slash) => format!("{}{}", &audio.url[..=slash], file_name),
+                None => file_name,
+            };
+            metadata.artwork = Some(relative_url(&self.url, &url));
+        }
+        Ok(metadata)
+    }
+
+    // a media embed from one of the `partials/` templates, with `root` added
+    // (values are escaped here when templates don't escape them)
+    fn render_partial(&self, name: &str, vars: serde_json::Value, context: &Web) -> anyhow::Result<String> {
+        let mut vars = match context.config.unescaped_templates {
+            true => escape_values(vars),
+            false => vars,
+        };
+        vars["root"] = relative_url(&self.url, "").into();
+        let html = context
+            .template_registry
+            .render(name, &vars)
+            .with_context(|| format!("rendering {}.hbs", name))?;
+        // embeds can be inline, so no trailing newline
+        Ok(html.trim_end_matches('\n').to_string())
+    }
+
+    // the captions file next to an audio or video file
+    fn captions<'a>(&self, context: &'a Web) -> Option<&'a Document> {
+        media::CAPTIONS_EXTENSIONS
+            .iter()
+            .find_map(|ext| context.find_doc(&normalize_path(&self.source_path.with_extension(ext))))
+    }
+
+    fn is_srt(&self) -> bool {
+        self.source_path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case(media::SRT_EXTENSION))
+    }
+
+    // url of captions as WebVTT, which for SubRip is the converted copy
+    fn captions_url(&self) -> String {
+        if !self.is_srt() {
+            return self.url.clone();
+        }
+        match self.url.rfind('.') {
+            Some(dot) => format!("{}.vtt", &self.url[..dot]),
+            None => format!("{}.vtt", self.url),
+        }
+    }
+
+    // SubRip captions converted to WebVTT, as (output path, url, content),
+    // unless there is already a `.vtt` file with the same name
+    pub fn converted_captions(&self, context: &Web) -> anyhow::Result<Option<(PathBuf, String, Vec<u8>)>> {
+        if !self.is_srt() || context.find_doc(&normalize_path(&self.source_path.with_extension("vtt"))).is_some() {
+            return Ok(None);
+        }
+        let srt = fs::read_to_string(&self.source_path)
+            .with_context(|| format!("reading captions {}", self.source_path.display()))?;
+        let vtt = media::srt_to_vtt(&srt);
+        Ok(Some((self.rel_outpath.with_extension("vtt"), self.captions_url(), vtt.into_bytes())))
+    }
+
+    // a gallery for `gallery: folder/` and a playlist for `playlist:` in
+    // front matter, which go after the page content
+    fn front_matter_blocks(&self, context: &Web) -> anyhow::Result<String> {
+        let front_matter = match &self.info {
+            DocumentInfo::Markdown { front_matter: Some(front_matter), .. } => front_matter,
+            _ => return Ok(String::new()),
+        };
+        let mut html = String::new();
+        if let Some(dir) = front_matter.get_str("gallery") {
+            html.push_str(&self.render_gallery(dir, context).context("gallery")?);
+        }
+        if let Some(playlist) = front_matter.vars.get("playlist") {
+            html.push_str(&self.front_matter_playlist(playlist, context).context("playlist")?);
+        }
+        Ok(html)
+    }
+
+    // `playlist:` is a folder of audio files or a list of them
+    fn front_matter_playlist(&self, playlist: &serde_json::Value, context: &Web) -> anyhow::Result<String> {
+        let (name, tracks) = match playlist {
+            serde_json::Value::String(dir) => {
+                let source_dir = self.source_dir(dir, context);
+                let name = source_dir.file_name().map(|name| name.to_string_lossy().to_string());
+                (name, self.audio_in_dir(&source_dir, context))
+            }
+            serde_json::Value::Array(paths) => {
+                let mut tracks = Vec::new();
+                for path in paths {
+                    let path = path.as_str().context("expected a list of file paths")?;
+                    match self.resolve_link(path, context) {
+                        Some(doc) => tracks.push(doc),
+                        None => bail!("file not found: {}", path),
+                    }
+                }
+                (None, tracks)
+            }
+            _ => bail!("expected a folder or a list of files"),
+        };
+        if tracks.is_empty() {
+            bail!("no audio files found");
+        }
+        let name = match name {
+            Some(name) => name,
+            None => self.file_stem()?.to_string(),
+        };
+        let mut warnings = Vec::new();
+        let html = self.render_playlist(&name, "", &tracks, context, &mut warnings)?;
+        for warning in warnings {
+            println!("warning: {}: {}", self.source_path.display(), warning);
+        }
+        Ok(html)
+    }
+
+    // audio files directly in a (normalized) source folder
+    fn audio_in_dir<'a>(&self, dir: &Path, context: &'a Web) -> Vec<&'a Document> {
+        context
+            .docs_in_dir(dir)
+            .into_iter()
+            .filter(|doc| !doc.is_markdown() && is_audio_file(&CowStr::from(doc.url.as_str())))
+            .collect()
+    }
+
+    // a playlist of audio files, also written next to the page as
+    // `<name>.m3u` and `<name>.pls` (except for EPUB chapters)
+    fn render_playlist(
+        &self,
+        name: &str,
+        title: &str,
+        docs: &[&Document],
+        context: &Web,
+        warnings: &mut Vec<String>,
+    ) -> anyhow::Result<String> {
+        let mut tracks = Vec::new();
+        for doc in docs {
+            let metadata = self.audio_metadata(doc, context).unwrap_or_else(|e| {
+                warnings.push(format!("{:#}", e));
+                AudioMetadata::default()
+            });
+            tracks.push(Track {
+                url: relative_url(&self.url, &doc.url),
+                mimetype: get_mimetype(&get_ext(&CowStr::from(doc.url.as_str()))),
+                title: match metadata.title {
+                    Some(title) => title,
+                    None => doc.file_stem()?.to_string(),
+                },
+                artist: metadata.artist,
+                duration: metadata.duration,
+                duration_text: metadata.duration_text,
+            });
+        }
+        if context.epub {
+            return Ok(playlist::html(title, &tracks, None, true));
+        }
+        let out_dir = context.out_path.join(self.rel_outpath.parent().unwrap_or_else(|| Path::new("")));
+        fs::create_dir_all(&out_dir)?;
+        let (m3u, pls) = (format!("{}.m3u", name), format!("{}.pls", name));
+        fs::write(out_dir.join(&m3u), playlist::m3u(&tracks))?;
+        fs::write(out_dir.join(&pls), playlist::pls(&tracks))?;
+        Ok(playlist::html(title, &tracks, Some((&m3u, &pls)), false))
+    }
+
+    // whether the file is an image to publish without its metadata
+    fn strips_metadata(&self, context: &Web) -> bool {
+        context.config.strip_metadata && metadata::is_strippable_image(&self.source_path)
+    }
+
+    fn stripped_image(&self) -> anyhow::Result<Vec<u8>> {
+        let bytes = fs::read(&self.source_path)?;
+        metadata::strip_metadata(&bytes)
+            .with_context(|| format!("removing metadata from {}", self.source_path.display()))
+    }
+
+    // content for an EPUB: markdown as an XHTML document, other files
+    // unchanged (except for image metadata with `strip_metadata`)
+    pub fn epub_content(&self, title: &str, context: &Web) -> anyhow::Result<Vec<u8>> {
+        match &self.info {
+            DocumentInfo::Other if self.strips_metadata(context) => self.stripped_image(),
+            DocumentInfo::Other => Ok(fs::read(&self.source_path)?),
+            DocumentInfo::Markdown { text, .. } => {
+                let mut body = Vec::new();
+                let render_info = self.write_html(&mut body, text, 0, &mut HashSet::new(), context)?;
+                self.report(&render_info, context)?;
+                body.extend(self.front_matter_blocks(context)?.into_bytes());
+                let xhtml = format!(
+                    "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<!DOCTYPE html>\n\
+                     <html xmlns=\"http://www.w3.org/1999/xhtml\" xmlns:epub=\"http://www.idpf.org/2007/ops\">\n\
+                     <head>\n<title>{}</title>\n</head>\n<body>\n{}</body>\n</html>\n",
+                    escape_html(title),
+                    String::from_utf8(body)?
+                );
+                Ok(xhtml.into_bytes())
             }
         }
+    }
+
+    // print problems found while rendering, failing on broken links with `strict_links`
+    fn report(&self, render_info: &RenderInfo, context: &Web) -> anyhow::Result<()> {
+        if !render_info.broken_links.is_empty() {
+            if context.config.strict_links {
+                bail!("broken links:\n{}", render_info.broken_links.join("\n"));
+            }
+            for broken_link in &render_info.broken_links {
+                println!("warning: {}", broken_link);
+            }
+        }
+        for warning in &render_info.warnings {
+            println!("warning: {}", warning);
+        }
         Ok(())
     }
+
     pub fn is_markdown(&self) -> bool {
         match self.info {
             DocumentInfo::Markdown { .. } => true,
@@ -170,55 +865,672 @@ impl Document {
         false
     }
 
-    // private utility function
-    fn write_html<W: Write>(out_writer: W, markdown: &String) -> anyhow::Result<()> {
+    // render a shortcode with its `shortcodes/<name>.hbs` template. Named
+    // arguments are available to the template by name, along with `args`
+    // (positional arguments), `inner` (the rendered body) and `root`.
+    // Returns the html and the plain text of the body, for the search index
+    fn render_shortcode(
+        &self,
+        shortcode: &Shortcode,
+        context: &Web,
+        heading_ids: &mut HashSet<String>,
+        broken_links: &mut Vec<String>,
+        warnings: &mut Vec<String>,
+    ) -> anyhow::Result<(String, String)> {
+        let template_name = format!("{}/{}", SHORTCODE_TEMPLATE_DIR, shortcode.name);
+        let has_template = context.template_registry.has_template(&template_name);
+        if shortcode.name == GALLERY_SHORTCODE {
+            let dir = match shortcode.args.first().or_else(|| shortcode.named.get("dir")) {
+                Some(dir) => dir,
+                None => bail!("no folder given, like {{{{< gallery photos/ >}}}}"),
+            };
+            return Ok((self.render_gallery(dir, context)?, String::new()));
+        }
+        if !has_template && shortcode.name != ADMONITION_SHORTCODE {
+            bail!("template {}.hbs not found", template_name);
+        }
+        let mut vars: serde_json::Map<String, serde_json::Value> = shortcode
+            .named
+            .iter()
+            .map(|(key, value)| (key.clone(), value.clone().into()))
+            .collect();
+        vars.insert("args".into(), shortcode.args.clone().into());
+        // arguments are escaped even when templates aren't, like partial values
+        if context.config.unescaped_templates {
+            vars = vars.into_iter().map(|(key, value)| (key, escape_values(value))).collect();
+        }
+        let mut text = String::new();
+        if let Some((offset, inner)) = &shortcode.inner {
+            let mut html = Vec::new();
+            let inner_info = self.write_html(&mut html, inner, *offset, heading_ids, context)?;
+            broken_links.extend(inner_info.broken_links);
+            warnings.extend(inner_info.warnings);
+            text = inner_info.text.body;
+            vars.insert("inner".into(), String::from_utf8(html)?.into());
+        }
+        vars.insert("root".into(), relative_url(&self.url, "").into());
+        if !has_template {
+            // built-in admonition markup
+            let kind = shortcode.named.get("kind").map(|kind| kind.as_str()).unwrap_or("note");
+            let title = shortcode.named.get("title").map(|title| title.as_str());
+            let inner = vars.get("inner").and_then(|inner| inner.as_str()).unwrap_or_default();
+            let html = format!(
+                "{}{}{}",
+                admonition::open_html(kind, title, context.epub),
+                inner,
+                admonition::close_html(context.epub)
+            );
+            return Ok((html, text));
+        }
+        Ok((context.template_registry.render(&template_name, &vars)?, text))
+    }
+
+    // a source folder given relative to the page (or to the site with a
+    // leading `/`), normalized
+    fn source_dir(&self, dir: &str, context: &Web) -> PathBuf {
+        let source_dir = match dir.strip_prefix('/') {
+            Some(site_dir) => context.in_path.join(site_dir),
+            None => self.source_path.parent().unwrap_or_else(|| Path::new("")).join(dir),
+        };
+        normalize_path(&source_dir)
+    }
+
+    // the images in a gallery folder, with thumbnails written to the output
+    fn gallery_images(&self, dir: &str, context: &Web) -> anyhow::Result<Vec<GalleryImage>> {
+        let source_dir = self.source_dir(dir, context);
+        let captions = gallery::read_captions(&source_dir)?;
+        let mut images = Vec::new();
+        for doc in context.docs_in_dir(&source_dir) {
+            if doc.is_markdown() || !images::has_dimensions(&doc.source_path) {
+                continue;
+            }
+            // EPUB chapters can only use files in the book, so show the originals
+            let (thumbnail, width, height) = if images::is_resizable_image(&doc.source_path) && !context.epub {
+                images::thumbnail(doc, gallery::THUMBNAIL_WIDTH, &context.out_path, &context.image_cache)
+                    .with_context(|| format!("making thumbnail of {}", doc.source_path.display()))?
+            } else {
+                let (width, height) = images::dimensions(&doc.source_path)?;
+                (doc.url.clone(), width, height)
+            };
+            let file_name = doc.source_path.file_name().unwrap_or_default().to_string_lossy();
+            let caption = captions.get(file_name.as_ref()).unwrap_or(&gallery::Caption::None);
+            let alt = caption
+                .alt()
+                .or(caption.caption())
+                .map(String::from)
+                .unwrap_or_else(|| doc.file_stem().unwrap_or_default().to_string());
+            images.push(GalleryImage {
+                url: relative_url(&self.url, &doc.url),
+                thumbnail: relative_url(&self.url, &thumbnail),
+                width,
+                height,
+                caption: caption.caption().map(String::from),
+                alt,
+            });
+        }
+        if images.is_empty() {
+            bail!("no images found for gallery in {}", source_dir.display());
+        }
+        Ok(images)
+    }
+
+    // gallery markup from the `shortcodes/gallery.hbs` template, or built in
+    fn render_gallery(&self, dir: &str, context: &Web) -> anyhow::Result<String> {
+        let images = self.gallery_images(dir, context)?;
+        let template_name = format!("{}/{}", SHORTCODE_TEMPLATE_DIR, GALLERY_SHORTCODE);
+        if !context.template_registry.has_template(&template_name) {
+            return Ok(gallery::html(&images));
+        }
+        let vars = serde_json::json!({
+            "images": images,
+            "root": relative_url(&self.url, ""),
+        });
+        Ok(context.template_registry.render(&template_name, &vars)?)
+    }
+
+    // raw html and link urls from the markdown filtered with `--sanitize`,
+    // with a warning for each removal
+    fn sanitize_event<'a>(
+        &self,
+        event: Event<'a>,
+        sanitizer: &mut Sanitizer,
+        offset: usize,
+        warnings: &mut Vec<String>,
+    ) -> Event<'a> {
+        let mut removed = Vec::new();
+        let event = match event {
+            Event::Html(html) => Event::Html(sanitizer.clean(&html, &mut removed).into()),
+            Event::Start(Tag::Link(link_type, url, title)) if !sanitize::is_safe_url(&url) => {
+                removed.push(format!("link to \"{}\"", url));
+                Event::Start(Tag::Link(link_type, "#".into(), title))
+            }
+            Event::Start(Tag::Image(link_type, url, title)) if !sanitize::is_safe_url(&url) => {
+                removed.push(format!("image \"{}\"", url));
+                Event::Start(Tag::Image(link_type, "".into(), title))
+            }
+            event => event,
+        };
+        for removal in removed {
+            let line = self.line_number(offset);
+            warnings.push(format!("{}:{}: sanitized: removed {}", self.source_path.display(), line, removal));
+        }
+        event
+    }
+
+    // a shortcode with unsafe urls removed from its arguments, like
+    // {{< figure src="javascript:..." >}}
+    fn sanitize_shortcode(&self, shortcode: &Shortcode, base_offset: usize, warnings: &mut Vec<String>) -> Shortcode {
+        let mut shortcode = shortcode.clone();
+        for (key, value) in shortcode.named.iter_mut() {
+            if sanitize::URL_ARGUMENTS.contains(&key.as_str()) && !sanitize::is_safe_url(value) {
+                let line = self.line_number(base_offset + shortcode.start);
+                let path = self.source_path.display();
+                warnings.push(format!("{}:{}: sanitized: removed {} \"{}\" of {}", path, line, key, value, shortcode.name));
+                value.clear();
+            }
+        }
+        shortcode
+    }
+
+    // `base_offset` is where `markdown` starts in the document text, for the
+    // body of a shortcode, and `heading_ids` holds the ids of headings in the
+    // page rendered so far, so shortcode bodies don't repeat them
+    fn write_html<W: Write>(
+        &self,
+        out_writer: W,
+        markdown: &str,
+        base_offset: usize,
+        heading_ids: &mut HashSet<String>,
+        context: &Web,
+    ) -> anyhow::Result<RenderInfo> {
         // Set up pulldown_cmark options and parser.
-        let mut options = pulldown_cmark::Options::empty();
-        // Strikethroughs are not part of the CommonMark standard
-        // so must be enabled explicitly (TODO: maybe configure?)
-        options.insert(pulldown_cmark::Options::ENABLE_STRIKETHROUGH);
+        let extensions = self.markdown_extensions(&context.config)?;
+        let options = extensions.options();
 
-        let mut parser = MarkdownParser::new_ext(&markdown, options);
+        // the page's own headings get their ids before those in shortcode
+        // bodies (rendered below), so reserve them from an outline of the
+        // markdown with shortcodes left empty
+        let outline = Preprocessed::new(markdown, options, extensions.math, &mut |_| Ok(Default::default()));
+        let before = heading_ids.clone();
+        let reserved: HashSet<String> = toc::collect_headings(&outline.text, options, heading_ids, &|text| {
+            outline.source_text(text)
+        })
+        .into_iter()
+        .map(|heading| heading.id)
+        .collect();
+
+        // math and shortcodes are rendered before parsing
+        let mut broken_links = Vec::new();
+        let mut warnings = Vec::new();
+        let pre = Preprocessed::new(markdown, options, extensions.math, &mut |shortcode| {
+            if context.config.sanitize {
+                let shortcode = self.sanitize_shortcode(shortcode, base_offset, &mut warnings);
+                return self.render_shortcode(&shortcode, context, heading_ids, &mut broken_links, &mut warnings);
+            }
+            self.render_shortcode(shortcode, context, heading_ids, &mut broken_links, &mut warnings)
+        });
+        for (offset, warning) in &pre.warnings {
+            let line = self.line_number(base_offset + offset);
+            warnings.push(format!("{}:{}: {}", self.source_path.display(), line, warning));
+        }
+
+        // headings are collected first so each gets a unique id, which is
+        // the reserved one unless a shortcode failed and left headings behind
+        heading_ids.retain(|id| before.contains(id) || !reserved.contains(id));
+        let headings = toc::collect_headings(&pre.text, options, heading_ids, &|text| pre.source_text(text));
+        let mut heading_ids = headings.iter().map(|heading| heading.id.as_str());
+        let mut current_heading_id = None;
+
+        // search text is taken before code, math and shortcodes become html
+        let search_events: Vec<Event> = MarkdownParser::new_ext(&pre.text, options)
+            .map(|event| match event {
+                Event::Text(text) => Event::Text(pre.search_text(&text).into()),
+                event => event,
+            })
+            .collect();
+        let search_text = PlainText::from_events(&admonition::replace_alerts(search_events, false));
+
+        let mut parser = MarkdownParser::new_ext(&pre.text, options).into_offset_iter();
 
         let mut new_event_list: Vec<Event> = Vec::new();
-        while let Some(event) = parser.next() {
+        // local images by rewritten url, with their size and any resized versions
+        let mut local_images: HashMap<String, LocalImage> = HashMap::new();
+        // transcripts waiting for the end of the current block
+        let mut transcripts: Vec<String> = Vec::new();
+        let mut reopen_paragraph = false;
+        let mut sanitizer = context.config.sanitize.then(Sanitizer::default);
+        while let Some((event, range)) = parser.next() {
+            let event = match &mut sanitizer {
+                Some(sanitizer) => {
+                    let offset = base_offset + pre.original_offset(range.start);
+                    self.sanitize_event(event, sanitizer, offset, &mut warnings)
+                }
+                None => event,
+            };
+            // after a playlist, the rest of its paragraph continues in a new one
+            if reopen_paragraph {
+                reopen_paragraph = false;
+                if let Event::End(Tag::Paragraph) = event {
+                    continue;
+                }
+                new_event_list.push(Event::Start(Tag::Paragraph));
+            }
             let next_event = match event {
-                Event::Start(Tag::Link(link_type, url, title)) => {
-                    let md_suffix = ".md";
-                    if url.ends_with(md_suffix) {
-                        let new_url = format!("{}.html", url.trim_end_matches(md_suffix));
-                        Event::Start(Tag::Link(link_type, new_url.into(), title))
-                    } else if is_audio_file(&url) {
-                        let link_text = if let Some(next_event) = parser.next() {
-                            if let Event::Text(text) = next_event {
-                                parser.next();  // skip Event::End
-                                text
-                            } else {
-                                // no text event, just Event::End
-                                Cow::Borrowed("#").into()
+                Event::Start(Tag::Heading(level, _, classes)) => {
+                    current_heading_id = heading_ids.next();
+                    Event::Start(Tag::Heading(level, current_heading_id, classes))
+                }
+                Event::End(Tag::Heading(..)) if context.config.heading_anchors => {
+                    if let Some(id) = current_heading_id {
+                        let anchor = format!("<a class=\"anchor\" href=\"#{}\" aria-hidden=\"true\">¶</a>", id);
+                        new_event_list.push(Event::Html(anchor.into()));
+                    }
+                    event
+                }
+                Event::Start(Tag::CodeBlock(kind)) if context.highlighter.is_some() => {
+                    let mut code = String::new();
+                    while let Some((Event::Text(text), _)) = parser.next() {
+                        code.push_str(&text);
+                    }
+                    let info = match kind {
+                        CodeBlockKind::Fenced(info) => CodeInfo::parse(&info),
+                        CodeBlockKind::Indented => CodeInfo::default(),
+                    };
+                    let highlighter = context.highlighter.as_ref().expect("highlighter");
+                    Event::Html(highlighter.highlight(&code, &info)?.into())
+                }
+                Event::Start(Tag::Image(link_type, url, title)) => {
+                    let offset = base_offset + pre.original_offset(range.start);
+                    let target = self.resolve_link(url.split(['?', '#']).next().unwrap_or_default(), context);
+                    let url = self.rewrite_url(url, offset, context, &mut broken_links);
+                    if let Some(doc) = target {
+                        let responsive = context.images.get(&doc.url);
+                        let size = match responsive {
+                            Some(image) => Some((image.width, image.height)),
+                            None if images::has_dimensions(&doc.source_path) => {
+                                match images::dimensions(&doc.source_path) {
+                                    Ok(size) => Some(size),
+                                    Err(e) => {
+                                        let line = self.line_number(offset);
+                                        let path = self.source_path.display();
+                                        warnings.push(format!("{}:{}: could not read size of {}: {:#}", path, line, url, e));
+                                        None
+                                    }
+                                }
                             }
+                            None => None,
+                        };
+                        local_images.insert(url.to_string(), LocalImage { size, responsive });
+                    }
+                    Event::Start(Tag::Image(link_type, url, title))
+                }
+                Event::Start(Tag::Link(link_type, url, title)) => {
+                    let offset = base_offset + pre.original_offset(range.start);
+                    let link_path = url.split(['?', '#']).next().unwrap_or_default();
+                    let target = self.resolve_link(link_path, context);
+                    // a folder of audio files without an index page is a playlist
+                    let playlist_tracks = match (target, link_path.ends_with('/') && !is_external_url(&url)) {
+                        (None, true) => self.audio_in_dir(&self.source_dir(link_path, context), context),
+                        _ => Vec::new(),
+                    };
+                    if !playlist_tracks.is_empty() {
+                        let link_text = plain_link_text(&mut parser);
+                        let dir = link_path.trim_end_matches('/');
+                        let name = dir.rsplit('/').next().filter(|name| !name.is_empty() && *name != "..").unwrap_or("playlist");
+                        let mut playlist_warnings = Vec::new();
+                        let html = self.render_playlist(name, &link_text, &playlist_tracks, context, &mut playlist_warnings)?;
+                        for warning in playlist_warnings {
+                            let line = self.line_number(offset);
+                            warnings.push(format!("{}:{}: {}", self.source_path.display(), line, warning));
+                        }
+                        // the playlist is a block, so it replaces a paragraph holding
+                        // just the link, or splits the paragraph around it
+                        if let Some(Event::Start(Tag::Paragraph)) = new_event_list.last() {
+                            new_event_list.pop();
                         } else {
-                            Cow::Borrowed("").into()
+                            new_event_list.push(Event::End(Tag::Paragraph));
+                        }
+                        reopen_paragraph = true;
+                        new_event_list.push(Event::Html(html.into()));
+                        continue;
+                    }
+                    let url = self.rewrite_url(url, offset, context, &mut broken_links);
+                    if is_audio_file(&url) || is_video_file(&url) {
+                        // the player falls back to a link with the plain link text
+                        let mut link_text = plain_link_text(&mut parser);
+                        if link_text.is_empty() {
+                            link_text.push('#');
+                        }
+                        let captions = target.and_then(|doc| doc.captions(context));
+                        let link = MediaLink {
+                            url: url.to_string(),
+                            title: title.to_string(),
+                            mimetype: get_mimetype(&get_ext(&url)),
+                            link_text,
+                            captions: captions.map(|captions| relative_url(&self.url, &captions.captions_url())),
                         };
-                        let my_link_text= format!("<a href=\"{}\" title=\"{}\" class=\"audio\"><span class=\"fa-solid fa-play\">{}</span></a>",
-                                 &url, &title, &link_text);
-                        let my_ext = get_ext(&url);
-                        let my_mimetype = get_mimetype(&my_ext);
-                        let my_html= format!("<audio controls><source src=\"{}\" type=\"{}\">Your browser does not support the audio element. {}</audio>",
-                                &url, my_mimetype, &my_link_text);
-                        Event::Html(my_html.into())
-
+                        let html = if is_video_file(&url) {
+                            let extras = match target {
+                                Some(doc) => self.video_extras(doc, context),
+                                None => VideoExtras::default(),
+                            };
+                            self.render_partial(VIDEO_PARTIAL, media::video_vars(&link, &extras)?, context)?
+                        } else {
+                            let metadata = match target.map(|doc| self.audio_metadata(doc, context)) {
+                                Some(Ok(metadata)) => metadata,
+                                Some(Err(e)) => {
+                                    let line = self.line_number(offset);
+                                    warnings.push(format!("{}:{}: {:#}", self.source_path.display(), line, e));
+                                    AudioMetadata::default()
+                                }
+                                None => AudioMetadata::default(),
+                            };
+                            self.render_partial(AUDIO_PARTIAL, media::audio_vars(&link, &metadata)?, context)?
+                        };
+                        if let (Some(captions), true) = (captions, context.config.transcripts) {
+                            match fs::read_to_string(&captions.source_path) {
+                                Ok(text) => transcripts.push(media::transcript_html(&media::cue_texts(&text))),
+                                Err(e) => {
+                                    let line = self.line_number(offset);
+                                    let path = self.source_path.display();
+                                    warnings.push(format!("{}:{}: could not read {}: {}", path, line, captions.url, e));
+                                }
+                            }
+                        }
+                        Event::Html(html.into())
                     } else {
                         Event::Start(Tag::Link(link_type, url, title))
                     }
                 }
                 _ => event,
             };
+            // transcripts go after the block holding the player, or at the
+            // end of a list item or table cell
+            let flush_before = matches!(next_event, Event::End(Tag::Item) | Event::End(Tag::TableCell));
+            let flush_after = matches!(next_event, Event::End(Tag::Paragraph) | Event::End(Tag::Heading(..)));
+            if flush_before && !transcripts.is_empty() {
+                new_event_list.push(Event::Html(transcripts.concat().into()));
+                transcripts.clear();
+            }
             new_event_list.push(next_event);
+            if flush_after && !transcripts.is_empty() {
+                new_event_list.push(Event::Html(transcripts.concat().into()));
+                transcripts.clear();
+            }
+        };
+        if !transcripts.is_empty() {
+            new_event_list.push(Event::Html(transcripts.concat().into()));
+        }
+        if let Some(sanitizer) = &mut sanitizer {
+            let mut removed = Vec::new();
+            sanitizer.finish(&mut removed);
+            for removal in removed {
+                warnings.push(format!("{}: sanitized: removed {}", self.source_path.display(), removal));
+            }
+        }
+
+        let toc = toc::build_toc(&headings);
+        let new_event_list = toc::replace_toc_marker(new_event_list, &toc::toc_html(&toc));
+        let new_event_list = pre.replace_placeholders(new_event_list);
+        let new_event_list = admonition::replace_alerts(new_event_list, context.epub);
+        let new_event_list = if extensions.figures {
+            let (new_event_list, figure_warnings) = figure::make_figures(new_event_list);
+            for warning in figure_warnings {
+                warnings.push(format!("{}: {}", self.source_path.display(), warning));
+            }
+            new_event_list
+        } else {
+            new_event_list
         };
 
+        let new_event_list = images::replace_images(new_event_list, &|image| {
+            let relative = |site_url: &str| relative_url(&self.url, site_url);
+            let embed = match local_images.get(&image.src) {
+                Some(local_image) => local_image.embed(image, context.config.image_sizes.as_deref(), &relative),
+                // images from elsewhere aren't lazily loaded
+                None => ImageEmbed { lazy: false, ..image.embed(None) },
+            };
+            Ok(Some(self.render_partial(IMAGE_PARTIAL, serde_json::to_value(embed)?, context)?))
+        })?;
+
+        let render_info = RenderInfo {
+            text: search_text,
+            broken_links,
+            warnings,
+            toc,
+        };
         pulldown_cmark::html::write_html(out_writer, new_event_list.into_iter())?;
-        Ok(())
+        Ok(render_info)
+    }
+
+}
+
+// every string in template variables escaped as html
+fn escape_values(value: serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::String(text) => escape_html(&text).into(),
+        serde_json::Value::Array(values) => values.into_iter().map(escape_values).collect(),
+        serde_json::Value::Object(map) => map.into_iter().map(|(key, value)| (key, escape_values(value))).collect(),
+        value => value,
+    }
+}
+
+// the plain text of a link, consuming its events up to the end of the link
+fn plain_link_text<'a>(parser: &mut impl Iterator<Item = (Event<'a>, Range<usize>)>) -> String {
+    let mut link_text = String::new();
+    for (event, _) in parser.by_ref() {
+        match event {
+            Event::End(Tag::Link(..)) => break,
+            Event::Text(text) | Event::Code(text) => link_text.push_str(&text),
+            _ => {}
+        }
+    }
+    link_text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_front_matter_date_formats() {
+        for (input, expected) in [
+            ("2022-10-01", "2022-10-01 00:00:00"),
+            ("2022-10-01 09:30:00", "2022-10-01 09:30:00"),
+        ] {
+            let mut text = format!("---\ndate: {}\n---\n# Hi\n", input);
+            let front_matter = FrontMatter::split_yml_from_string(&mut text)
+                .expect("parse")
+                .expect("front matter");
+            let date = front_matter.date().expect("date").expect("some date");
+            assert_eq!(date.to_string(), expected);
+            assert_eq!(text, "# Hi\n");
+        }
     }
 
+    #[test]
+    fn test_front_matter_markdown_extensions() {
+        let config = Config {
+            markdown_extensions: MarkdownExtensions::from_names(&["tables", "strikethrough"]).expect("names"),
+            ..Default::default()
+        };
+        let markdown = "---\nmarkdown_extensions:\n  tables: false\n  smart_punctuation: true\n---\n# Hi\n";
+        let doc = markdown_doc("site/a.md", markdown, &config).expect("document");
+        let extensions = doc.markdown_extensions(&config).expect("extensions");
+        // the front matter only changes the extensions it names
+        assert!(!extensions.tables && extensions.smart_punctuation && extensions.strikethrough);
+        let doc = markdown_doc("site/b.md", "# Hi\n", &config).expect("document");
+        assert_eq!(doc.markdown_extensions(&config).expect("extensions"), config.markdown_extensions);
+
+        let markdown = "---\nmarkdown_extensions:\n  emoji: true\n---\n# Hi\n";
+        let doc = markdown_doc("site/c.md", markdown, &config).expect("document");
+        let e = doc.markdown_extensions(&config).expect_err("unknown extension");
+        assert!(format!("{:#}", e).contains("unknown markdown extension 'emoji'"), "{:#}", e);
+    }
+
+    fn markdown_doc(path: &str, markdown: &str, config: &Config) -> anyhow::Result<Document> {
+        Document::from_text(Path::new(path), Some(markdown.into()), Path::new("site"), config)
+    }
+
+    // a site in the temp directory with the given markdown files, loaded
+    // but not generated
+    fn test_web(name: &str, files: &[(&str, &str)], config: Config) -> Web<'static> {
+        let dir = std::env::temp_dir().join("webgenr-tests").join(name);
+        if dir.exists() {
+            fs::remove_dir_all(&dir).expect("remove old test site");
+        }
+        for (path, text) in files {
+            let path = dir.join("markdown").join(path);
+            fs::create_dir_all(path.parent().expect("parent")).expect("create dir");
+            fs::write(&path, text).expect("write markdown");
+        }
+        Web::new(dir.join("markdown"), dir.join("_website"), dir.join("templates"), config).expect("web")
+    }
+
+    // `url` from `from` rewritten, with any broken link messages
+    fn rewrite(web: &Web, from: &str, url: &str) -> (String, Vec<String>) {
+        let doc = web.find_doc(&normalize_path(&web.in_path.join(from))).expect("document");
+        let mut broken_links = Vec::new();
+        let url = doc.rewrite_url(url.into(), 0, web, &mut broken_links).to_string();
+        (url, broken_links)
+    }
+
+    #[test]
+    fn test_rewrite_url() {
+        let files = [
+            ("page.md", "# Page\n"),
+            ("other.md", "# Other\n"),
+            ("my notes.md", "# Notes\n"),
+            ("about.md", "---\npermalink: /about/\n---\n# About\n"),
+            ("guide/index.md", "# Guide\n"),
+            ("guide/intro.md", "# Intro\n"),
+            ("empty/photo.png", ""),
+        ];
+        let web = test_web("rewrite-url", &files, Config::default());
+        let link = |from: &str, url: &str| rewrite(&web, from, url).0;
+        assert_eq!(link("page.md", "other.md#sec"), "other.html#sec");
+        assert_eq!(link("page.md", "other.md?x=1"), "other.html?x=1");
+        assert_eq!(link("guide/intro.md", "../other.md"), "../other.html");
+        assert_eq!(link("page.md", "guide/"), "guide/index.html");
+        assert_eq!(link("page.md", "/guide/intro.md"), "guide/intro.html");
+        assert_eq!(link("page.md", "my%20notes.md"), "my notes.html");
+        assert_eq!(link("guide/intro.md", "../about.md"), "../about/");
+        assert_eq!(link("page.md", "https://example.com/a.md"), "https://example.com/a.md");
+
+        // broken links are reported and left as written
+        let (url, broken_links) = rewrite(&web, "page.md", "missing.md#sec");
+        assert_eq!(url, "missing.md#sec");
+        assert_eq!(broken_links.len(), 1);
+        assert!(broken_links[0].ends_with("page.md:1: link target not found: missing.md#sec"), "{:?}", broken_links);
+        let (url, broken_links) = rewrite(&web, "page.md", "empty/");
+        assert_eq!(url, "empty/");
+        assert!(broken_links[0].ends_with("link target not found: empty/ (no index.md in that folder)"));
+    }
+
+    #[test]
+    fn test_heading_ids_in_shortcodes() {
+        let markdown = "# Notes\n\n:::tip\n## Notes\n:::\n\n:::note\n## Notes\n:::\n";
+        let web = test_web("heading-ids", &[("page.md", markdown)], Config::default());
+        let doc = web.find_doc(&normalize_path(&web.in_path.join("page.md"))).expect("document");
+        let text = match &doc.info {
+            DocumentInfo::Markdown { text, .. } => text.clone(),
+            DocumentInfo::Other => panic!("not markdown"),
+        };
+        let mut html = Vec::new();
+        doc.write_html(&mut html, &text, 0, &mut HashSet::new(), &web).expect("render");
+        let html = String::from_utf8(html).expect("utf-8");
+        let ids: Vec<&str> = html.split(" id=\"").skip(1).map(|rest| &rest[..rest.find('"').expect("quote")]).collect();
+        assert_eq!(ids, vec!["notes", "notes-1", "notes-2"], "{}", html);
+    }
+
+    #[test]
+    fn test_skipped_pages() {
+        let files = [
+            ("page.md", "# Page\n"),
+            ("draft.md", "---\ndraft: true\n---\n# Draft\n"),
+            ("future.md", "---\ndate: 2999-01-01\n---\n# Future\n"),
+        ];
+        let web = test_web("skipped", &files, Config::default());
+        let path = |name: &str| normalize_path(&web.in_path.join(name));
+        assert!(web.find_doc(&path("page.md")).is_some());
+        assert!(web.find_doc(&path("draft.md")).is_none());
+        assert!(web.find_doc(&path("future.md")).is_none());
+        assert_eq!(web.skip_reason(&path("draft.md")), Some("draft (use --drafts to include)"));
+        let (url, broken_links) = rewrite(&web, "page.md", "draft.md");
+        assert_eq!(url, "draft.md");
+        assert!(
+            broken_links[0].ends_with("link to a page that isn't published: draft.md, draft (use --drafts to include)"),
+            "{:?}",
+            broken_links
+        );
+        let (_, broken_links) = rewrite(&web, "page.md", "future.md");
+        assert!(broken_links[0].contains("dated in the future"), "{:?}", broken_links);
+
+        let config = Config {
+            include_drafts: true,
+            include_future: true,
+            ..Default::default()
+        };
+        let web = test_web("skipped-included", &files, config);
+        assert_eq!(rewrite(&web, "page.md", "draft.md"), ("draft.html".to_string(), Vec::new()));
+        assert_eq!(rewrite(&web, "page.md", "future.md"), ("future.html".to_string(), Vec::new()));
+    }
+
+    #[test]
+    fn test_strict_links() {
+        let files = [("page.md", "[x](missing.md)\n")];
+        let render_info = RenderInfo {
+            broken_links: vec!["page.md:1: link target not found: missing.md".into()],
+            ..Default::default()
+        };
+        let web = test_web("links-warn", &files, Config::default());
+        let doc = web.find_doc(&normalize_path(&web.in_path.join("page.md"))).expect("document");
+        assert!(doc.report(&render_info, &web).is_ok());
+        let config = Config {
+            strict_links: true,
+            ..Default::default()
+        };
+        let web = test_web("links-strict", &files, config);
+        let doc = web.find_doc(&normalize_path(&web.in_path.join("page.md"))).expect("document");
+        let e = doc.report(&render_info, &web).expect_err("strict links");
+        assert!(e.to_string().contains("link target not found: missing.md"), "{}", e);
+    }
+
+    #[test]
+    fn test_permalink_slug_and_pattern() {
+        let config = Config {
+            permalink: Some("/:year/:month/:slug/".into()),
+            ..Default::default()
+        };
+        let markdown = "---\ndate: 2022-10-01\nslug: first\n---\n# Hi\n";
+        let doc = markdown_doc("site/notes/post.md", markdown, &config).expect("document");
+        assert_eq!(doc.url, "2022/10/first/");
+        // without a site pattern the slug replaces the file name
+        let doc = markdown_doc("site/notes/post.md", markdown, &Config::default()).expect("document");
+        assert_eq!(doc.url, "notes/first/");
+    }
+
+    #[test]
+    fn test_permalink_outside_site() {
+        let markdown = "---\ntitle: Escape\npermalink: /../../escaped/\n---\n# Hi\n";
+        let e = markdown_doc("site/post.md", markdown, &Config::default()).err().expect("error");
+        assert!(e.to_string().starts_with("site/post.md:3: invalid permalink: "), "{}", e);
+        assert!(site_path_to_outpath("a/./b/../c.html").is_err());
+    }
+
+    #[test]
+    fn test_alias_outside_site() {
+        let markdown = "---\naliases: [old.html, /../../escaped.html]\n---\n# Hi\n";
+        let doc = markdown_doc("site/notes/post.md", markdown, &Config::default()).expect("document");
+        let e = doc.aliases(Path::new("site")).err().expect("error");
+        assert!(e.to_string().starts_with("site/notes/post.md:2: invalid alias: "), "{}", e);
+    }
+
+    #[test]
+    fn test_front_matter_error_line() {
+        let mut text = String::from("---\ntitle: ok\ntags: [one\n---\n# Hi\n");
+        let e = FrontMatter::split_yml_from_string(&mut text).err().expect("yaml error");
+        let message = front_matter_error(Path::new("post.md"), e).to_string();
+        assert!(message.starts_with("post.md:4:1: invalid front matter: "), "{}", message);
+    }
 }
@@ -0,0 +1,212 @@
+use crate::util::escape_html;
+use pulldown_cmark::{Event, Tag};
+use crate::images::ImageAttributes;
+use std::collections::HashMap;
+use std::ops::Range;
+
+// With the `figures` extension, an image alone in its paragraph becomes a
+// numbered `<figure>` when it has a caption, taken from the image title
+//   ![Butterfly](butterfly.png "A butterfly in SF")
+// or from a following paragraph starting with `Caption:`. An id can follow
+// the image, `![Butterfly](butterfly.png){#fig:butterfly}`, and
+// `@fig:butterfly` in the text links to the figure as "Figure 1".
+
+const CAPTION_PREFIX: &str = "Caption:";
+const REFERENCE_PREFIX: &str = "@fig:";
+
+struct Figure<'a> {
+    // events replaced by the figure
+    replaced: Range<usize>,
+    // the image's own events, with any attributes other than the id
+    image: Vec<Event<'a>>,
+    id: Option<String>,
+    caption: Vec<Event<'a>>,
+}
+
+// a figure for the paragraph starting at `start`, if it holds just an image
+fn find_figure<'a>(events: &[Event<'a>], start: usize) -> Option<Figure<'a>> {
+    let title = match events.get(start + 1)? {
+        Event::Start(Tag::Image(_, _, title)) => title.clone(),
+        _ => return None,
+    };
+    let image_end = start + 1 + events[start + 1..].iter().position(|e| matches!(e, Event::End(Tag::Image(..))))?;
+    let mut after = String::new();
+    let mut paragraph_end = image_end + 1;
+    while let Some(Event::Text(text)) = events.get(paragraph_end) {
+        after.push_str(text);
+        paragraph_end += 1;
+    }
+    if !matches!(events.get(paragraph_end)?, Event::End(Tag::Paragraph)) {
+        return None;
+    }
+    // only an attribute block, like `{#fig:cat}`, may follow the image
+    let attributes = match after.trim_end() {
+        "" => ImageAttributes::default(),
+        after => match ImageAttributes::parse_prefix(after)? {
+            (attributes, len) if len == after.len() => attributes,
+            _ => return None,
+        },
+    };
+    let mut image = events[start + 1..image_end + 1].to_vec();
+    let other_attributes = attributes.without_id();
+    if !other_attributes.is_empty() {
+        image.push(Event::Text(other_attributes.into()));
+    }
+    let mut figure = Figure {
+        replaced: start..paragraph_end + 1,
+        image,
+        id: attributes.id,
+        caption: Vec::new(),
+    };
+    if !title.is_empty() {
+        figure.caption.push(Event::Text(title));
+    } else if let (Some(Event::Start(Tag::Paragraph)), Some(Event::Text(text))) =
+        (events.get(paragraph_end + 1), events.get(paragraph_end + 2))
+    {
+        if let Some(caption) = text.strip_prefix(CAPTION_PREFIX) {
+            let caption_start = paragraph_end + 2;
+            let caption_end = caption_start
+                + events[caption_start..].iter().position(|e| matches!(e, Event::End(Tag::Paragraph)))?;
+            figure.caption.push(Event::Text(caption.trim_start().to_string().into()));
+            figure.caption.extend(events[caption_start + 1..caption_end].iter().cloned());
+            figure.replaced.end = caption_end + 1;
+        }
+    }
+    if figure.id.is_none() && figure.caption.is_empty() {
+        return None;
+    }
+    Some(figure)
+}
+
+// replace `@fig:id` references in text with links to the figure
+fn replace_references<'a>(
+    text: &str,
+    numbers: &HashMap<String, usize>,
+    events: &mut Vec<Event<'a>>,
+    warnings: &mut Vec<String>,
+) {
+    let mut rest = text;
+    while let Some(start) = rest.find(REFERENCE_PREFIX) {
+        let id_len = rest[start + 1..]
+            .find(|c: char| !(c.is_alphanumeric() || c == '-' || c == '_' || c == ':'))
+            .unwrap_or(rest.len() - start - 1);
+        let id = rest[start + 1..start + 1 + id_len].trim_end_matches(':');
+        let end = start + 1 + id.len();
+        match numbers.get(id) {
+            Some(number) => {
+                if start > 0 {
+                    events.push(Event::Text(rest[..start].to_string().into()));
+                }
+                let link = format!("<a href=\"#{}\" class=\"figure-ref\">Figure {}</a>", escape_html(id), number);
+                events.push(Event::Html(link.into()));
+            }
+            None => {
+                warnings.push(format!("unknown figure reference @{}", id));
+                events.push(Event::Text(rest[..end].to_string().into()));
+            }
+        }
+        rest = &rest[end..];
+    }
+    if !rest.is_empty() {
+        events.push(Event::Text(rest.to_string().into()));
+    }
+}
+
+// wrap captioned images in numbered figures and link `@fig:id` references;
+// returns warnings for references to unknown figures
+pub fn make_figures<'a>(events: Vec<Event<'a>>) -> (Vec<Event<'a>>, Vec<String>) {
+    let mut figures = Vec::new();
+    let mut numbers = HashMap::new();
+    let mut i = 0;
+    while i < events.len() {
+        if let Event::Start(Tag::Paragraph) = events[i] {
+            if let Some(figure) = find_figure(&events, i) {
+                if let Some(id) = &figure.id {
+                    numbers.insert(id.clone(), figures.len() + 1);
+                }
+                i = figure.replaced.end;
+                figures.push(figure);
+                continue;
+            }
+        }
+        i += 1;
+    }
+
+    let mut result = Vec::with_capacity(events.len());
+    let mut warnings = Vec::new();
+    let mut figures = figures.into_iter().enumerate().peekable();
+    // inside code blocks, links and image alt text, references are left alone
+    let mut literal_depth = 0;
+    let mut i = 0;
+    while i < events.len() {
+        if let Some((n, figure)) = figures.next_if(|(_, figure)| figure.replaced.start == i) {
+            let open = match &figure.id {
+                Some(id) => format!("<figure id=\"{}\">\n", escape_html(id)),
+                None => "<figure>\n".to_string(),
+            };
+            result.push(Event::Html(open.into()));
+            result.extend(figure.image);
+            if figure.caption.is_empty() {
+                let caption = format!("\n<figcaption><span class=\"figure-number\">Figure {}</span></figcaption>", n + 1);
+                result.push(Event::Html(caption.into()));
+            } else {
+                let caption = format!("\n<figcaption><span class=\"figure-number\">Figure {}:</span> ", n + 1);
+                result.push(Event::Html(caption.into()));
+                for event in figure.caption {
+                    match event {
+                        Event::Text(text) => replace_references(&text, &numbers, &mut result, &mut warnings),
+                        event => result.push(event),
+                    }
+                }
+                result.push(Event::Html("</figcaption>".into()));
+            }
+            result.push(Event::Html("\n</figure>\n".into()));
+            i = figure.replaced.end;
+            continue;
+        }
+        match &events[i] {
+            Event::Start(Tag::CodeBlock(_)) | Event::Start(Tag::Link(..)) | Event::Start(Tag::Image(..)) => {
+                literal_depth += 1;
+                result.push(events[i].clone());
+            }
+            Event::End(Tag::CodeBlock(_)) | Event::End(Tag::Link(..)) | Event::End(Tag::Image(..)) => {
+                literal_depth -= 1;
+                result.push(events[i].clone());
+            }
+            Event::Text(text) if literal_depth == 0 && text.contains(REFERENCE_PREFIX) => {
+                replace_references(text, &numbers, &mut result, &mut warnings);
+            }
+            event => result.push(event.clone()),
+        }
+        i += 1;
+    }
+    (result, warnings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pulldown_cmark::{html, Parser};
+
+    #[test]
+    fn test_make_figures() {
+        let markdown = "See @fig:cat and @fig:dog.\n\n\
+                        ![Cat](cat.png \"A cat\"){#fig:cat}\n\n\
+                        ![Dog](dog.png)\n\n\
+                        ![Bird](bird.png)\n\n\
+                        Caption: A *bird*\n";
+        let (events, warnings) = make_figures(Parser::new(markdown).collect());
+        let mut out = String::new();
+        html::push_html(&mut out, events.into_iter());
+        assert_eq!(
+            out,
+            "<p>See <a href=\"#fig:cat\" class=\"figure-ref\">Figure 1</a> and @fig:dog.</p>\n\
+             <figure id=\"fig:cat\">\n<img src=\"cat.png\" alt=\"Cat\" title=\"A cat\" />\n\
+             <figcaption><span class=\"figure-number\">Figure 1:</span> A cat</figcaption>\n</figure>\n\
+             <p><img src=\"dog.png\" alt=\"Dog\" /></p>\n\
+             <figure>\n<img src=\"bird.png\" alt=\"Bird\" />\n\
+             <figcaption><span class=\"figure-number\">Figure 2:</span> A <em>bird</em></figcaption>\n</figure>\n"
+        );
+        assert_eq!(warnings, vec!["unknown figure reference @fig:dog"]);
+    }
+}
@@ -0,0 +1,129 @@
+use crate::util::escape_html;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+// Galleries show thumbnails of every image in a folder, each linking to the
+// full image, for a page with `gallery: photos/` front matter or with the
+// shortcode `{{< gallery photos/ >}}`. Captions and alt text come from an
+// optional `gallery.yaml` in the folder:
+//   cat.jpg: A cat asleep
+//   dog.jpg:
+//     caption: A very good dog
+//     alt: Dog catching a frisbee
+// A `shortcodes/gallery.hbs` template can replace the markup below; it
+// receives `images`, each with `url`, `thumbnail`, `width`, `height`,
+// `caption` and `alt`.
+
+pub const GALLERY_SHORTCODE: &str = "gallery";
+pub const CAPTIONS_FILE: &str = "gallery.yaml";
+pub const THUMBNAIL_WIDTH: u32 = 400;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(untagged)]
+pub enum Caption {
+    #[default]
+    None,
+    Text(String),
+    Details {
+        caption: Option<String>,
+        alt: Option<String>,
+    },
+}
+
+impl Caption {
+    pub fn caption(&self) -> Option<&str> {
+        match self {
+            Caption::None => None,
+            Caption::Text(caption) => Some(caption),
+            Caption::Details { caption, .. } => caption.as_deref(),
+        }
+    }
+
+    pub fn alt(&self) -> Option<&str> {
+        match self {
+            Caption::Details { alt, .. } => alt.as_deref(),
+            _ => None,
+        }
+    }
+}
+
+// captions by file name from the folder's `gallery.yaml`, if there is one
+pub fn read_captions(dir: &Path) -> anyhow::Result<HashMap<String, Caption>> {
+    let path = dir.join(CAPTIONS_FILE);
+    if !path.exists() {
+        return Ok(HashMap::new());
+    }
+    let yaml = fs::read_to_string(&path)?;
+    Ok(serde_yaml::from_str::<Option<HashMap<String, Caption>>>(&yaml)?.unwrap_or_default())
+}
+
+// one image in a gallery, with urls relative to the page
+#[derive(Debug, Serialize)]
+pub struct GalleryImage {
+    pub url: String,
+    pub thumbnail: String,
+    // size of the thumbnail
+    pub width: u32,
+    pub height: u32,
+    pub caption: Option<String>,
+    pub alt: String,
+}
+
+// lightbox-ready markup: most lightbox scripts pick up links with
+// `data-lightbox` (grouped by its value) and show `data-caption`
+pub fn html(images: &[GalleryImage]) -> String {
+    let mut html = String::from("<div class=\"gallery\">\n");
+    for image in images {
+        let caption = image.caption.as_deref().map(escape_html);
+        html.push_str(&format!(
+            "<figure class=\"gallery-item\"><a href=\"{}\" data-lightbox=\"gallery\"{}>\
+             <img src=\"{}\" width=\"{}\" height=\"{}\" alt=\"{}\" loading=\"lazy\" decoding=\"async\" /></a>",
+            escape_html(&image.url),
+            match &caption {
+                Some(caption) => format!(" data-caption=\"{}\"", caption),
+                None => String::new(),
+            },
+            escape_html(&image.thumbnail),
+            image.width,
+            image.height,
+            escape_html(&image.alt)
+        ));
+        if let Some(caption) = caption {
+            html.push_str(&format!("<figcaption>{}</figcaption>", caption));
+        }
+        html.push_str("</figure>\n");
+    }
+    html.push_str("</div>\n");
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gallery_captions_and_html() {
+        let yaml = "cat.jpg: A <cat>\ndog.jpg:\n  caption: A dog\n  alt: Dog with a frisbee\n";
+        let captions: HashMap<String, Caption> = serde_yaml::from_str(yaml).expect("yaml");
+        assert_eq!(captions["cat.jpg"].caption(), Some("A <cat>"));
+        assert_eq!(captions["dog.jpg"].alt(), Some("Dog with a frisbee"));
+
+        let images = vec![GalleryImage {
+            url: "photos/cat.jpg".into(),
+            thumbnail: "photos/cat-400w.jpg".into(),
+            width: 400,
+            height: 300,
+            caption: captions["cat.jpg"].caption().map(String::from),
+            alt: "cat".into(),
+        }];
+        assert_eq!(
+            html(&images),
+            "<div class=\"gallery\">\n<figure class=\"gallery-item\">\
+             <a href=\"photos/cat.jpg\" data-lightbox=\"gallery\" data-caption=\"A &lt;cat&gt;\">\
+             <img src=\"photos/cat-400w.jpg\" width=\"400\" height=\"300\" alt=\"cat\" loading=\"lazy\" decoding=\"async\" /></a>\
+             <figcaption>A &lt;cat&gt;</figcaption></figure>\n</div>\n"
+        );
+    }
+}
@@ -0,0 +1,286 @@
+use crate::util::escape_html;
+use anyhow::bail;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+use syntect::easy::HighlightLines;
+use syntect::highlighting::{Color, FontStyle, Style, Theme, ThemeSet};
+use syntect::parsing::SyntaxSet;
+use syntect::util::LinesWithEndings;
+
+// stylesheet written to the output directory in `HighlightMode::Css`
+pub const HIGHLIGHT_CSS_FILE: &str = "style/highlight.css";
+
+// how highlighted code is styled
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum HighlightMode {
+    // `style` attributes on every span, no stylesheet needed
+    Inline,
+    // class names, with rules written to `HIGHLIGHT_CSS_FILE`
+    Css,
+    // leave code blocks as plain `<pre><code>`, as before highlighting
+    #[default]
+    None,
+}
+
+impl FromStr for HighlightMode {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "inline" => Ok(HighlightMode::Inline),
+            "css" => Ok(HighlightMode::Css),
+            "none" => Ok(HighlightMode::None),
+            _ => Err(format!("unknown highlight mode '{}', expected inline, css or none", s)),
+        }
+    }
+}
+
+pub const DEFAULT_THEME: &str = "InspiredGitHub";
+
+// language and options from a fenced code block's info string, like
+// ```rust linenos hl_lines=2,4-5
+// (Hugo-style braces are accepted too: ```rust {linenos=true hl_lines="2 4-5"})
+#[derive(Debug, Default, PartialEq)]
+pub struct CodeInfo {
+    pub lang: String,
+    pub line_numbers: bool,
+    pub highlight_lines: Vec<RangeInclusive<usize>>,
+}
+
+impl CodeInfo {
+    pub fn parse(info: &str) -> Self {
+        let info = info.replace(['{', '}'], " ");
+        let mut code_info = CodeInfo::default();
+        let mut words = info.split_whitespace().peekable();
+        if let Some(first) = words.peek() {
+            if !first.contains('=') {
+                code_info.lang = first.trim_end_matches(',').to_string();
+                words.next();
+            }
+        }
+        // hl_lines may continue over several words: hl_lines="2 4-5"
+        let mut in_hl_lines = false;
+        for word in words {
+            let word = word.trim_end_matches(',');
+            let (key, value) = match word.split_once('=') {
+                Some((key, value)) => (key, Some(value)),
+                None if in_hl_lines => ("hl_lines", Some(word)),
+                None => (word, None),
+            };
+            in_hl_lines = false;
+            match key {
+                "linenos" => code_info.line_numbers = value != Some("false"),
+                "hl_lines" => {
+                    let value = value.unwrap_or_default();
+                    in_hl_lines = value.starts_with('"') && !(value.len() > 1 && value.ends_with('"'));
+                    let ranges = value
+                        .split([',', ' '])
+                        .map(|part| part.trim_matches(|c| c == '"' || c == '[' || c == ']'));
+                    for part in ranges.filter(|part| !part.is_empty()) {
+                        let range = match part.split_once('-') {
+                            Some((start, end)) => start.parse().ok().zip(end.parse().ok()),
+                            None => part.parse().ok().map(|line| (line, line)),
+                        };
+                        if let Some((start, end)) = range {
+                            code_info.highlight_lines.push(start..=end);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        code_info
+    }
+
+    fn is_highlighted(&self, line_number: usize) -> bool {
+        self.highlight_lines.iter().any(|range| range.contains(&line_number))
+    }
+}
+
+fn hex(color: Color) -> String {
+    format!("#{:02x}{:02x}{:02x}", color.r, color.g, color.b)
+}
+
+// css declarations and a matching class name for a token style
+fn style_css(style: &Style) -> (String, String) {
+    let mut class = format!("hl-{}", &hex(style.foreground)[1..]);
+    let mut css = format!("color:{};", hex(style.foreground));
+    if style.font_style.contains(FontStyle::BOLD) {
+        class.push_str("-b");
+        css.push_str("font-weight:bold;");
+    }
+    if style.font_style.contains(FontStyle::ITALIC) {
+        class.push_str("-i");
+        css.push_str("font-style:italic;");
+    }
+    if style.font_style.contains(FontStyle::UNDERLINE) {
+        class.push_str("-u");
+        css.push_str("text-decoration:underline;");
+    }
+    (class, css)
+}
+
+const LINE_NUMBER_CSS: &str =
+    "display:inline-block;min-width:2em;margin-right:1em;text-align:right;opacity:0.5;user-select:none;";
+const HIGHLIGHTED_LINE_CSS: &str = "display:inline-block;width:100%;";
+const DEFAULT_LINE_HIGHLIGHT: Color = Color { r: 0xff, g: 0xf5, b: 0x9d, a: 0xff };
+
+pub struct Highlighter {
+    mode: HighlightMode,
+    syntax_set: SyntaxSet,
+    theme: Theme,
+    // classes used so far in `HighlightMode::Css`, with their css declarations
+    used_classes: RefCell<BTreeMap<String, String>>,
+}
+
+impl Highlighter {
+    // returns None for `HighlightMode::None`
+    pub fn new(mode: HighlightMode, theme_name: &str) -> anyhow::Result<Option<Self>> {
+        if mode == HighlightMode::None {
+            return Ok(None);
+        }
+        let mut theme_set = ThemeSet::load_defaults();
+        let theme = match theme_set.themes.remove(theme_name) {
+            Some(theme) => theme,
+            None => bail!(
+                "unknown highlight theme '{}', available themes: {}",
+                theme_name,
+                theme_set.themes.keys().cloned().collect::<Vec<_>>().join(", ")
+            ),
+        };
+        Ok(Some(Highlighter {
+            mode,
+            syntax_set: SyntaxSet::load_defaults_newlines(),
+            theme,
+            used_classes: RefCell::new(BTreeMap::new()),
+        }))
+    }
+
+    fn line_highlight(&self) -> Color {
+        self.theme.settings.line_highlight.unwrap_or(DEFAULT_LINE_HIGHLIGHT)
+    }
+
+    // html for a code block, replacing pulldown-cmark's `<pre><code>`
+    pub fn highlight(&self, code: &str, info: &CodeInfo) -> anyhow::Result<String> {
+        let syntax = self
+            .syntax_set
+            .find_syntax_by_token(&info.lang)
+            .unwrap_or_else(|| self.syntax_set.find_syntax_plain_text());
+        let mut highlight_lines = HighlightLines::new(syntax, &self.theme);
+
+        let mut html = String::from("<pre class=\"highlight\"");
+        if self.mode == HighlightMode::Inline {
+            html.push_str(" style=\"");
+            if let Some(background) = self.theme.settings.background {
+                html.push_str(&format!("background-color:{};", hex(background)));
+            }
+            if let Some(foreground) = self.theme.settings.foreground {
+                html.push_str(&format!("color:{};", hex(foreground)));
+            }
+            html.push('"');
+        }
+        html.push_str("><code");
+        if !info.lang.is_empty() {
+            html.push_str(&format!(" class=\"language-{}\"", escape_html(&info.lang)));
+        }
+        html.push('>');
+
+        for (i, line) in LinesWithEndings::from(code).enumerate() {
+            let line_number = i + 1;
+            let highlighted = info.is_highlighted(line_number);
+            match (highlighted, self.mode) {
+                (true, HighlightMode::Inline) => html.push_str(&format!(
+                    "<span class=\"line highlighted\" style=\"{}background-color:{};\">",
+                    HIGHLIGHTED_LINE_CSS,
+                    hex(self.line_highlight())
+                )),
+                (true, _) => html.push_str("<span class=\"line highlighted\">"),
+                (false, _) => html.push_str("<span class=\"line\">"),
+            }
+            if info.line_numbers {
+                match self.mode {
+                    HighlightMode::Inline => html.push_str(&format!(
+                        "<span class=\"line-number\" style=\"{}\">{}</span>",
+                        LINE_NUMBER_CSS, line_number
+                    )),
+                    _ => html.push_str(&format!("<span class=\"line-number\">{}</span>", line_number)),
+                }
+            }
+            for (style, text) in highlight_lines.highlight_line(line, &self.syntax_set)? {
+                let text = text.trim_end_matches(['\r', '\n']);
+                if text.is_empty() {
+                    continue;
+                }
+                if text.trim().is_empty() {
+                    html.push_str(text);
+                    continue;
+                }
+                let (class, css) = style_css(&style);
+                match self.mode {
+                    HighlightMode::Inline => html.push_str(&format!("<span style=\"{}\">", css)),
+                    _ => {
+                        html.push_str(&format!("<span class=\"{}\">", class));
+                        self.used_classes.borrow_mut().insert(class, css);
+                    }
+                }
+                html.push_str(&escape_html(text));
+                html.push_str("</span>");
+            }
+            html.push_str("</span>\n");
+        }
+        html.push_str("</code></pre>\n");
+        Ok(html)
+    }
+
+    // stylesheet for the classes used by `highlight` in `HighlightMode::Css`
+    pub fn css(&self) -> Option<String> {
+        if self.mode != HighlightMode::Css {
+            return None;
+        }
+        let mut css = String::from("pre.highlight {");
+        if let Some(background) = self.theme.settings.background {
+            css.push_str(&format!(" background-color: {};", hex(background)));
+        }
+        if let Some(foreground) = self.theme.settings.foreground {
+            css.push_str(&format!(" color: {};", hex(foreground)));
+        }
+        css.push_str(" }\n");
+        css.push_str(&format!(".highlight .line-number {{ {} }}\n", LINE_NUMBER_CSS));
+        css.push_str(&format!(
+            ".highlight .highlighted {{ {}background-color: {}; }}\n",
+            HIGHLIGHTED_LINE_CSS,
+            hex(self.line_highlight())
+        ));
+        for (class, declarations) in self.used_classes.borrow().iter() {
+            css.push_str(&format!(".highlight .{} {{ {} }}\n", class, declarations));
+        }
+        Some(css)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_code_info_parse() {
+        assert_eq!(
+            CodeInfo::parse("rust linenos hl_lines=2,4-5"),
+            CodeInfo {
+                lang: "rust".into(),
+                line_numbers: true,
+                highlight_lines: vec![2..=2, 4..=5],
+            }
+        );
+        assert_eq!(
+            CodeInfo::parse("python {linenos=false, hl_lines=\"1 3-4\"}"),
+            CodeInfo {
+                lang: "python".into(),
+                line_numbers: false,
+                highlight_lines: vec![1..=1, 3..=4],
+            }
+        );
+        assert_eq!(CodeInfo::parse(""), CodeInfo::default());
+    }
+}
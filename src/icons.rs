@@ -0,0 +1,54 @@
+use handlebars::{Context, Handlebars, Helper, HelperResult, Output, RenderContext, RenderError};
+use rust_embed::RustEmbed;
+
+// Icons are SVG files embedded in the binary and inlined where they are
+// used, with `{{icon "play"}}` in a template, so pages need no icon font
+// or script and work offline and in EPUB books. They are sized `1em` and
+// drawn in `currentColor`, so they follow the surrounding text.
+
+#[derive(RustEmbed)]
+#[folder = "icons/"]
+#[include = "*.svg"]
+struct Icons;
+
+pub const ICON_HELPER: &str = "icon";
+
+// the markup of a named icon, like "play"
+pub fn svg(name: &str) -> Option<String> {
+    let file = Icons::get(&format!("{}.svg", name))?;
+    Some(String::from_utf8_lossy(file.data.as_ref()).trim_end().to_string())
+}
+
+// `{{icon "name"}}`, failing the render for an unknown icon
+pub fn icon_helper(
+    h: &Helper,
+    _: &Handlebars,
+    _: &Context,
+    _: &mut RenderContext,
+    out: &mut dyn Output,
+) -> HelperResult {
+    let name = h
+        .param(0)
+        .and_then(|param| param.value().as_str())
+        .ok_or_else(|| RenderError::new("icon: expected an icon name"))?;
+    let svg = svg(name).ok_or_else(|| RenderError::new(format!("icon: no icon named '{}'", name)))?;
+    out.write(&svg)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_icon_helper() {
+        let mut handlebars = Handlebars::new();
+        handlebars.register_helper(ICON_HELPER, Box::new(icon_helper));
+        handlebars.register_template_string("t", "<a>{{icon \"play\"}}</a>").expect("template");
+        let html = handlebars.render("t", &()).expect("render");
+        assert!(html.starts_with("<a><svg ") && html.ends_with("</svg></a>"));
+        assert!(html.contains("class=\"icon icon-play\""));
+        handlebars.register_template_string("t", "{{icon \"nope\"}}").expect("template");
+        assert!(handlebars.render("t", &()).is_err());
+    }
+}
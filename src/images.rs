@@ -0,0 +1,608 @@
+use crate::document::Document;
+use crate::util::{get_ext, get_mimetype};
+use crate::Config;
+use anyhow::Context;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::imageops::FilterType;
+use image::metadata::Orientation;
+use image::{DynamicImage, ImageDecoder, ImageFormat, ImageReader};
+use pulldown_cmark::{Event, Tag};
+use serde::Serialize;
+use std::collections::hash_map::DefaultHasher;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io::{BufWriter, Cursor, Seek, Write};
+use std::path::{Path, PathBuf};
+
+// Responsive images: with `--image-widths`, jpeg, png and webp images are
+// also written at each configured width smaller than the original, and with
+// `--webp` as WebP too. Images in markdown then get `srcset`, `sizes`,
+// `width` and `height`. Variants are named like `photo-480w.jpg`, and a site
+// file with the same name is an error rather than being overwritten.
+// Processed files (and gallery thumbnails) are cached between builds in the
+// `images` folder of `--cache-dir` (`.webgenr-cache` in the source directory
+// by default), keyed by the source file's path, modification time and size
+// and the output options, since resizing is slow. Cached files a build that
+// resized images didn't use are removed.
+// Every local image in markdown also gets its intrinsic `width` and `height`,
+// to prevent layout shift, and `loading="lazy"`, unless it opts out with
+// an attribute block: `![Logo](logo.png){eager nosize}`.
+// Images are rendered with the `partials/image.hbs` template.
+
+pub const DEFAULT_CACHE_DIR: &str = ".webgenr-cache";
+const IMAGE_CACHE_DIR: &str = "images";
+pub const IMAGE_PARTIAL: &str = "partials/image";
+const JPEG_QUALITY: u8 = 85;
+const DEFAULT_SIZES: &str = "100vw";
+// bumped when the way variants are generated changes, to invalidate the cache
+const CACHE_VERSION: u32 = 3;
+
+// the cache directory of a site, remembering which files a build used
+#[derive(Debug)]
+pub struct ImageCache {
+    dir: PathBuf,
+    used: RefCell<HashSet<PathBuf>>,
+}
+
+impl ImageCache {
+    // the cache in `cache_dir`, which defaults to one in the source directory
+    pub fn new(in_path: &Path, cache_dir: Option<&Path>) -> Self {
+        let cache_dir = cache_dir.map(Path::to_path_buf).unwrap_or_else(|| in_path.join(DEFAULT_CACHE_DIR));
+        ImageCache {
+            dir: cache_dir.join(IMAGE_CACHE_DIR),
+            used: RefCell::new(HashSet::new()),
+        }
+    }
+
+    // path of a cached file, which may not exist yet
+    fn path(&self, name: &str) -> anyhow::Result<PathBuf> {
+        fs::create_dir_all(&self.dir)?;
+        let path = self.dir.join(name);
+        self.used.borrow_mut().insert(path.clone());
+        Ok(path)
+    }
+
+    // remove cached files this build didn't use, like variants of images
+    // that were changed or deleted
+    pub fn prune(&self) -> anyhow::Result<()> {
+        if !self.dir.exists() {
+            return Ok(());
+        }
+        let used = self.used.borrow();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.is_file() && !used.contains(&path) {
+                fs::remove_file(&path).with_context(|| format!("removing {}", path.display()))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// one generated file
+#[derive(Debug, Clone)]
+pub struct Variant {
+    // site-relative url, like "photos/cat-480w.jpg"
+    pub url: String,
+    pub width: u32,
+    pub webp: bool,
+}
+
+// an image from the site with its generated variants
+#[derive(Debug)]
+pub struct ResponsiveImage {
+    pub width: u32,
+    pub height: u32,
+    pub webp_source: bool,
+    pub variants: Vec<Variant>,
+}
+
+pub fn is_resizable_image(path: &Path) -> bool {
+    let ext = path.extension().map(|ext| ext.to_string_lossy().to_lowercase());
+    matches!(ext.as_deref(), Some("jpg") | Some("jpeg") | Some("png") | Some("webp"))
+}
+
+pub(crate) fn encode<W: Write + Seek>(image: &DynamicImage, format: ImageFormat, mut writer: W) -> anyhow::Result<()> {
+    match format {
+        ImageFormat::Jpeg => {
+            DynamicImage::ImageRgb8(image.to_rgb8()).write_with_encoder(JpegEncoder::new_with_quality(writer, JPEG_QUALITY))?
+        }
+        // the image crate only encodes lossless WebP
+        ImageFormat::WebP => {
+            let image = if image.color().has_alpha() {
+                DynamicImage::ImageRgba8(image.to_rgba8())
+            } else {
+                DynamicImage::ImageRgb8(image.to_rgb8())
+            };
+            image.write_with_encoder(WebPEncoder::new_lossless(writer))?
+        }
+        _ => image.write_to(&mut writer, format)?,
+    }
+    Ok(())
+}
+
+// a source image being resized, decoded only when a variant isn't cached
+struct SourceImage<'a> {
+    doc: &'a Document,
+    bytes: Vec<u8>,
+    format: ImageFormat,
+    orientation: Orientation,
+    // size as displayed, after orientation
+    width: u32,
+    height: u32,
+    // hash of the source file and options, naming cached files
+    hash: u64,
+    decoded: Option<DynamicImage>,
+}
+
+impl<'a> SourceImage<'a> {
+    fn open(doc: &'a Document) -> anyhow::Result<Self> {
+        let metadata = fs::metadata(&doc.source_path)?;
+        let bytes = fs::read(&doc.source_path)?;
+        let reader = ImageReader::new(Cursor::new(&bytes)).with_guessed_format()?;
+        let format = reader.format().context("unknown image format")?;
+        let (orientation, (width, height)) = {
+            let mut decoder = reader.into_decoder()?;
+            let orientation = decoder.orientation()?;
+            (orientation, oriented(decoder.dimensions(), orientation))
+        };
+        let mut hasher = DefaultHasher::new();
+        (CACHE_VERSION, JPEG_QUALITY).hash(&mut hasher);
+        (&doc.source_path, metadata.modified()?, metadata.len()).hash(&mut hasher);
+        let hash = hasher.finish();
+        Ok(SourceImage {
+            doc,
+            bytes,
+            format,
+            orientation,
+            width,
+            height,
+            hash,
+            decoded: None,
+        })
+    }
+
+    // write the image at `target_width` (as WebP with `webp`) next to the
+    // original in the output directory, reusing the cached file if there is one
+    fn variant(&mut self, target_width: u32, webp: bool, out_path: &Path, cache: &ImageCache) -> anyhow::Result<Variant> {
+        let doc = self.doc;
+        let ext = match doc.source_path.extension() {
+            Some(ext) => ext.to_string_lossy().to_lowercase(),
+            None => String::new(),
+        };
+        let (variant_ext, variant_format) = if webp {
+            ("webp", ImageFormat::WebP)
+        } else {
+            (ext.as_str(), self.format)
+        };
+        let cache_path = cache.path(&format!("{:016x}-{}w.{}", self.hash, target_width, variant_ext))?;
+        if !cache_path.exists() {
+            if self.decoded.is_none() {
+                let mut image = image::load_from_memory_with_format(&self.bytes, self.format)?;
+                image.apply_orientation(self.orientation);
+                self.decoded = Some(image);
+            }
+            let source = self.decoded.as_ref().expect("decoded image");
+            let resized = if target_width < self.width {
+                source.resize(target_width, u32::MAX, FilterType::Lanczos3)
+            } else {
+                source.clone()
+            };
+            info!("image-> {}\t{}w", doc.source_path.display(), target_width);
+            fs::File::create(&cache_path)
+                .map_err(anyhow::Error::from)
+                .and_then(|file| encode(&resized, variant_format, BufWriter::new(file)))
+                .with_context(|| format!("writing {}", cache_path.display()))?;
+        }
+
+        let file_name = variant_file_name(doc, target_width, variant_ext);
+        let url_dir = match doc.url.rfind('/') {
+            Some(slash) => &doc.url[..=slash],
+            None => "",
+        };
+        let out_dir = out_path.join(doc.rel_outpath.parent().unwrap_or_else(|| Path::new("")));
+        fs::create_dir_all(&out_dir)?;
+        fs::copy(&cache_path, out_dir.join(&file_name))?;
+        Ok(Variant {
+            url: format!("{}{}", url_dir, file_name),
+            width: target_width,
+            webp,
+        })
+    }
+}
+
+// name of a variant, like "cat-480w.jpg", next to the original
+fn variant_file_name(doc: &Document, width: u32, ext: &str) -> String {
+    let stem = doc.source_path.file_stem().unwrap_or_default().to_string_lossy();
+    format!("{}-{}w.{}", stem, width, ext)
+}
+
+// the (width, webp) variants to generate for an image `width` pixels wide
+fn variant_targets(width: u32, webp_source: bool, config: &Config) -> Vec<(u32, bool)> {
+    let mut targets: Vec<(u32, bool)> = config
+        .image_widths
+        .iter()
+        .filter(|w| **w < width)
+        .map(|w| (*w, webp_source))
+        .collect();
+    if config.image_webp && !webp_source {
+        targets.extend(config.image_widths.iter().filter(|w| **w < width).map(|w| (*w, true)));
+        targets.push((width, true));
+    }
+    targets.sort_unstable();
+    targets.dedup();
+    targets
+}
+
+// site-relative output paths of the variants `process_images` will write for
+// an image, to check them against other files; empty if the image can't be
+// read (which `process_images` reports)
+pub fn variant_outpaths(doc: &Document, config: &Config) -> Vec<PathBuf> {
+    let width = match dimensions(&doc.source_path) {
+        Ok((width, _)) => width,
+        Err(_) => return Vec::new(),
+    };
+    let ext = match doc.source_path.extension() {
+        Some(ext) => ext.to_string_lossy().to_lowercase(),
+        None => String::new(),
+    };
+    let webp_source = ext == "webp";
+    let dir = doc.rel_outpath.parent().unwrap_or_else(|| Path::new(""));
+    variant_targets(width, webp_source, config)
+        .into_iter()
+        .map(|(width, webp)| dir.join(variant_file_name(doc, width, if webp { "webp" } else { &ext })))
+        .collect()
+}
+
+// generate the variants of one image into the output directory, reusing
+// files from the cache directory where possible
+fn process_image(doc: &Document, config: &Config, out_path: &Path, cache: &ImageCache) -> anyhow::Result<ResponsiveImage> {
+    let mut source = SourceImage::open(doc)?;
+    let width = source.width;
+    let webp_source = source.format == ImageFormat::WebP;
+    let targets = variant_targets(width, webp_source, config);
+
+    let mut variants = Vec::new();
+    for (target_width, webp) in targets {
+        variants.push(source.variant(target_width, webp, out_path, cache)?);
+    }
+    Ok(ResponsiveImage {
+        width,
+        height: source.height,
+        webp_source,
+        variants,
+    })
+}
+
+// a gallery thumbnail: the image scaled down to `max_width` (or the image
+// itself if it is narrower), with its site url and size
+pub fn thumbnail(doc: &Document, max_width: u32, out_path: &Path, cache: &ImageCache) -> anyhow::Result<(String, u32, u32)> {
+    let mut source = SourceImage::open(doc)?;
+    if source.width <= max_width {
+        return Ok((doc.url.clone(), source.width, source.height));
+    }
+    let variant = source.variant(max_width, false, out_path, cache)?;
+    let height = (source.height as u64 * max_width as u64 / source.width as u64).max(1) as u32;
+    Ok((variant.url, max_width, height))
+}
+
+// process every resizable image in the site, keyed by url
+pub fn process_images(
+    docs: &[Document],
+    config: &Config,
+    out_path: &Path,
+    cache: &ImageCache,
+) -> anyhow::Result<HashMap<String, ResponsiveImage>> {
+    let mut images = HashMap::new();
+    for doc in docs.iter().filter(|doc| !doc.is_markdown() && is_resizable_image(&doc.source_path)) {
+        let image = process_image(doc, config, out_path, cache)
+            .with_context(|| format!("processing image {}", doc.source_path.display()))?;
+        images.insert(doc.url.clone(), image);
+    }
+    Ok(images)
+}
+
+impl ResponsiveImage {
+    // `srcset` value for the webp or original-format variants, including the
+    // original itself; `relative` turns site urls into page-relative ones
+    fn srcset(&self, webp: bool, src: &str, relative: &dyn Fn(&str) -> String) -> String {
+        let mut candidates: Vec<String> = self
+            .variants
+            .iter()
+            .filter(|variant| variant.webp == webp)
+            .map(|variant| format!("{} {}w", relative(&variant.url), variant.width))
+            .collect();
+        if webp == self.webp_source {
+            candidates.push(format!("{} {}w", src, self.width));
+        }
+        candidates.join(", ")
+    }
+
+    // `srcset` and size for an image, with webp variants of a non-webp
+    // image offered as `webp_srcset` (for a `<picture>` source)
+    pub fn embed(&self, image: &Image, sizes: Option<&str>, relative: &dyn Fn(&str) -> String) -> ImageEmbed {
+        let mut embed = image.embed(Some((self.width, self.height)));
+        embed.srcset = Some(self.srcset(self.webp_source, &image.src, relative));
+        embed.sizes = Some(sizes.unwrap_or(DEFAULT_SIZES).to_string());
+        if !self.webp_source && self.variants.iter().any(|variant| variant.webp) {
+            embed.webp_srcset = Some(self.srcset(true, &image.src, relative));
+        }
+        embed
+    }
+}
+
+// size of an image as displayed, after its EXIF orientation is applied
+fn oriented((width, height): (u32, u32), orientation: Orientation) -> (u32, u32) {
+    match orientation {
+        Orientation::Rotate90 | Orientation::Rotate270 | Orientation::Rotate90FlipH | Orientation::Rotate270FlipH => {
+            (height, width)
+        }
+        _ => (width, height),
+    }
+}
+
+// whether `dimensions` can read the size of an image
+pub fn has_dimensions(path: &Path) -> bool {
+    let ext = path.extension().map(|ext| ext.to_string_lossy().to_lowercase());
+    is_resizable_image(path) || matches!(ext.as_deref(), Some("gif") | Some("svg"))
+}
+
+// intrinsic size of a local image, read from its header (or for svg, from
+// the `width`/`height` or `viewBox` of the root element)
+pub fn dimensions(path: &Path) -> anyhow::Result<(u32, u32)> {
+    let is_svg = path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("svg"));
+    if !is_svg {
+        let mut decoder = ImageReader::open(path)?.with_guessed_format()?.into_decoder()?;
+        let orientation = decoder.orientation()?;
+        return Ok(oriented(decoder.dimensions(), orientation));
+    }
+    let svg = fs::read_to_string(path)?;
+    let start = svg.find("<svg").context("no <svg> element")?;
+    let end = start + svg[start..].find('>').context("unterminated <svg> element")?;
+    let element = &svg[start..end];
+    let attribute = |name: &str| {
+        let pattern = format!(" {}=", name);
+        let value = &element[element.find(&pattern)? + pattern.len()..];
+        let quote = value.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+        let value = &value[1..];
+        Some(value[..value.find(quote)?].trim().to_string())
+    };
+    // lengths in px (or without a unit) only, "100%" or "10em" can't be used
+    let length = |name: &str| attribute(name)?.trim_end_matches("px").parse::<f64>().ok();
+    if let (Some(width), Some(height)) = (length("width"), length("height")) {
+        return Ok((width.round() as u32, height.round() as u32));
+    }
+    let view_box = attribute("viewBox").context("no width, height or viewBox")?;
+    let numbers: Vec<f64> = view_box
+        .split([' ', ','])
+        .filter(|part| !part.is_empty())
+        .map(|part| part.parse::<f64>())
+        .collect::<Result<_, _>>()?;
+    match numbers.as_slice() {
+        [_, _, width, height] => Ok((width.round() as u32, height.round() as u32)),
+        _ => anyhow::bail!("invalid viewBox '{}'", view_box),
+    }
+}
+
+// `{#id eager nosize}` written right after an image: an id for the image
+// (or its figure), and opting out of lazy loading or of width and height
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ImageAttributes {
+    pub id: Option<String>,
+    pub eager: bool,
+    pub nosize: bool,
+}
+
+impl ImageAttributes {
+    // attributes at the start of `text`, with the length of the `{...}` block
+    pub fn parse_prefix(text: &str) -> Option<(Self, usize)> {
+        let len = text.strip_prefix('{')?.find('}')? + 2;
+        let mut attributes = ImageAttributes::default();
+        for word in text[1..len - 1].split_whitespace() {
+            match word {
+                "eager" => attributes.eager = true,
+                "nosize" => attributes.nosize = true,
+                word => attributes.id = Some(word.strip_prefix('#').filter(|id| !id.is_empty())?.to_string()),
+            }
+        }
+        Some((attributes, len))
+    }
+
+    // the attribute block, without the id
+    pub fn without_id(&self) -> String {
+        let words: Vec<&str> = [(self.eager, "eager"), (self.nosize, "nosize")]
+            .iter()
+            .filter(|(enabled, _)| *enabled)
+            .map(|(_, word)| *word)
+            .collect();
+        if words.is_empty() {
+            return String::new();
+        }
+        format!("{{{}}}", words.join(" "))
+    }
+}
+
+// an image found in markdown
+#[derive(Debug, Default)]
+pub struct Image {
+    pub src: String,
+    pub alt: String,
+    pub title: String,
+    pub attributes: ImageAttributes,
+}
+
+// what `partials/image.hbs` gets to render an image
+#[derive(Debug, Default, Serialize)]
+pub struct ImageEmbed {
+    pub url: String,
+    pub alt: String,
+    pub title: String,
+    pub mimetype: String,
+    // intrinsic size, unless the image opted out
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub srcset: Option<String>,
+    pub sizes: Option<String>,
+    pub webp_srcset: Option<String>,
+    pub id: Option<String>,
+    pub lazy: bool,
+}
+
+impl Image {
+    // the image with its intrinsic size, lazily loaded unless it opted out
+    pub fn embed(&self, dimensions: Option<(u32, u32)>) -> ImageEmbed {
+        let dimensions = dimensions.filter(|_| !self.attributes.nosize);
+        ImageEmbed {
+            url: self.src.clone(),
+            alt: self.alt.clone(),
+            title: self.title.clone(),
+            mimetype: get_mimetype(&get_ext(&self.src.as_str().into())),
+            width: dimensions.map(|(width, _)| width),
+            height: dimensions.map(|(_, height)| height),
+            id: self.attributes.id.clone(),
+            lazy: !self.attributes.eager,
+            ..Default::default()
+        }
+    }
+}
+
+// an image from the site referenced in markdown
+pub struct LocalImage<'a> {
+    pub size: Option<(u32, u32)>,
+    pub responsive: Option<&'a ResponsiveImage>,
+}
+
+impl LocalImage<'_> {
+    pub fn embed(&self, image: &Image, sizes: Option<&str>, relative: &dyn Fn(&str) -> String) -> ImageEmbed {
+        match self.responsive {
+            Some(responsive) => responsive.embed(image, sizes, relative),
+            None => image.embed(self.size),
+        }
+    }
+}
+
+// replace image events, with any attribute block following them, by html
+// from `image_html`; where it returns None only the attribute block is removed
+pub fn replace_images<'a>(
+    events: Vec<Event<'a>>,
+    image_html: &dyn Fn(&Image) -> anyhow::Result<Option<String>>,
+) -> anyhow::Result<Vec<Event<'a>>> {
+    let mut result = Vec::with_capacity(events.len());
+    let mut i = 0;
+    while i < events.len() {
+        if let Event::Start(Tag::Image(_, url, title)) = &events[i] {
+            let mut image = Image {
+                src: url.to_string(),
+                title: title.to_string(),
+                ..Default::default()
+            };
+            let mut depth = 0;
+            let mut end = i + 1;
+            while let Some(event) = events.get(end) {
+                match event {
+                    Event::Start(Tag::Image(..)) => depth += 1,
+                    Event::End(Tag::Image(..)) if depth == 0 => break,
+                    Event::End(Tag::Image(..)) => depth -= 1,
+                    Event::Text(text) | Event::Code(text) => image.alt.push_str(text),
+                    _ => {}
+                }
+                end += 1;
+            }
+            let mut rest_of_text = None;
+            if let Some(Event::Text(text)) = events.get(end + 1) {
+                if let Some((attributes, len)) = ImageAttributes::parse_prefix(text) {
+                    image.attributes = attributes;
+                    rest_of_text = Some(&text[len..]);
+                }
+            }
+            match image_html(&image)? {
+                Some(html) => result.push(Event::Html(html.into())),
+                None => result.extend(events[i..=end.min(events.len() - 1)].iter().cloned()),
+            }
+            i = end + 1;
+            if let Some(rest) = rest_of_text {
+                if !rest.is_empty() {
+                    result.push(Event::Text(rest.to_string().into()));
+                }
+                i += 1;
+            }
+            continue;
+        }
+        result.push(events[i].clone());
+        i += 1;
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_responsive_image_html() {
+        let image = ResponsiveImage {
+            width: 1600,
+            height: 900,
+            webp_source: false,
+            variants: vec![
+                Variant { url: "photos/cat-480w.jpg".into(), width: 480, webp: false },
+                Variant { url: "photos/cat-480w.webp".into(), width: 480, webp: true },
+                Variant { url: "photos/cat-1600w.webp".into(), width: 1600, webp: true },
+            ],
+        };
+        let relative = |url: &str| url.trim_start_matches("photos/").to_string();
+        let cat = Image {
+            src: "cat.jpg".into(),
+            alt: "A cat".into(),
+            ..Default::default()
+        };
+        let embed = image.embed(&cat, None, &relative);
+        assert_eq!(embed.srcset.as_deref(), Some("cat-480w.jpg 480w, cat.jpg 1600w"));
+        assert_eq!(embed.webp_srcset.as_deref(), Some("cat-480w.webp 480w, cat-1600w.webp 1600w"));
+        assert_eq!(embed.sizes.as_deref(), Some("100vw"));
+        assert_eq!((embed.width, embed.height), (Some(1600), Some(900)));
+        assert_eq!(embed.mimetype, "image/jpeg");
+        let mut handlebars = handlebars::Handlebars::new();
+        handlebars
+            .register_template_string("image", include_str!("../templates/partials/image.hbs"))
+            .expect("template");
+        assert_eq!(
+            handlebars.render("image", &embed).expect("render").trim_end(),
+            "<picture><source type=\"image/webp\" srcset=\"cat-480w.webp 480w, cat-1600w.webp 1600w\" sizes=\"100vw\" />\
+             <img src=\"cat.jpg\" srcset=\"cat-480w.jpg 480w, cat.jpg 1600w\" sizes=\"100vw\" width=\"1600\" height=\"900\" \
+             alt=\"A cat\" loading=\"lazy\" decoding=\"async\" /></picture>"
+        );
+    }
+
+    #[test]
+    fn test_image_cache_prune() {
+        let in_path = std::env::temp_dir().join("webgenr-tests").join("image-cache");
+        let cache_dir = in_path.join(DEFAULT_CACHE_DIR).join(IMAGE_CACHE_DIR);
+        if cache_dir.exists() {
+            fs::remove_dir_all(&cache_dir).expect("remove old cache");
+        }
+        let cache = ImageCache::new(&in_path, None);
+        let used = cache.path("0000000000000001-480w.jpg").expect("path");
+        assert!(used.starts_with(&cache_dir));
+        fs::write(&used, "new").expect("write");
+        let stale = cache_dir.join("0000000000000002-480w.jpg");
+        fs::write(&stale, "old").expect("write");
+        cache.prune().expect("prune");
+        assert!(used.exists());
+        assert!(!stale.exists());
+    }
+
+    #[test]
+    fn test_image_attributes() {
+        let (attributes, len) = ImageAttributes::parse_prefix("{#fig:cat eager} and").expect("attributes");
+        assert_eq!(attributes.id.as_deref(), Some("fig:cat"));
+        assert!(attributes.eager && !attributes.nosize);
+        assert_eq!(len, 16);
+        assert_eq!(attributes.without_id(), "{eager}");
+        assert!(ImageAttributes::parse_prefix("{lazy}").is_none());
+        assert!(ImageAttributes::parse_prefix(" {eager}").is_none());
+    }
+}
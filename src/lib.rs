@@ -1,8 +1,28 @@
 mod document;
 pub use self::document::Document;
 
+mod config;
+pub use self::config::{Config, MarkdownExtensions};
+
 mod web;
 mod util;
+mod search;
+mod redirect;
+mod toc;
+mod highlight;
+mod math;
+mod preprocess;
+mod shortcode;
+mod admonition;
+mod figure;
+mod gallery;
+mod icons;
+mod images;
+mod media;
+mod metadata;
+mod playlist;
+mod sanitize;
+pub use self::highlight::HighlightMode;
 pub use self::web::Web;
 
 extern crate pretty_env_logger;
@@ -1,6 +1,8 @@
 use anyhow::Result;
 use clap::{AppSettings, Parser};
-use webgenr::Web;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use webgenr::{Config, HighlightMode, MarkdownExtensions, Web};
 
 extern crate pretty_env_logger;
 
@@ -22,11 +24,120 @@ struct Cli {
 
     #[clap(long, short, action)]
     book: bool,
+
+    /// also write a prebuilt inverted index (search_inverted.json) for client-side search
+    #[clap(long, action)]
+    search_inverted_index: bool,
+
+    /// include documents marked `draft: true` in front matter
+    #[clap(long, action)]
+    drafts: bool,
+
+    /// include documents with a front matter `date` in the future
+    #[clap(long, action)]
+    future: bool,
+
+    /// url pattern for pages, using :year, :month, :day, :slug and :path,
+    /// for example "/:year/:month/:slug/"
+    #[clap(long, value_parser)]
+    permalink: Option<String>,
+
+    /// fail on links to missing internal pages, images or audio (default: warn)
+    #[clap(long, action)]
+    strict_links: bool,
+
+    /// add a "¶" permalink anchor to each heading
+    #[clap(long, action)]
+    heading_anchors: bool,
+
+    /// comma-separated markdown extensions: tables, footnotes, strikethrough,
+    /// tasklists, smart-punctuation, heading-attributes, math, figures
+    #[clap(long, value_parser, value_delimiter = ',', default_value = "strikethrough")]
+    markdown_extensions: Vec<String>,
+
+    /// syntax highlighting for code blocks: inline (style attributes),
+    /// css (writes style/highlight.css) or none
+    #[clap(long, value_parser, default_value = "none")]
+    highlight: HighlightMode,
+
+    /// syntax highlighting theme, for example InspiredGitHub, base16-ocean.dark,
+    /// base16-ocean.light, Solarized (dark), Solarized (light)
+    #[clap(long, value_parser)]
+    highlight_theme: Option<String>,
+
+    /// comma-separated widths to also resize images to, like 480,960,1600;
+    /// markdown images get a srcset (processed images are cached, see --cache-dir)
+    #[clap(long, value_parser, value_delimiter = ',')]
+    image_widths: Vec<u32>,
+
+    /// also write WebP versions of images, offered with <picture>
+    #[clap(long, action)]
+    webp: bool,
+
+    /// sizes attribute for images with a srcset (default: 100vw)
+    #[clap(long, value_parser)]
+    image_sizes: Option<String>,
+
+    /// directory for cached resized images and thumbnails
+    /// (default: .webgenr-cache in the source directory)
+    #[clap(long, value_parser)]
+    cache_dir: Option<PathBuf>,
+
+    /// remove EXIF and XMP metadata, like camera details and GPS position,
+    /// from jpeg, png and webp images as they are published
+    #[clap(long, action)]
+    strip_metadata: bool,
+
+    /// show the captions (.vtt or .srt next to the file) of audio and video
+    /// as a collapsible transcript below the player
+    #[clap(long, action)]
+    transcripts: bool,
+
+    /// load a FontAwesome kit script in pages, like
+    /// https://kit.fontawesome.com/<id>.js (built-in icons don't need it)
+    #[clap(long, value_parser)]
+    fontawesome_kit: Option<String>,
+
+    /// don't HTML-escape template variables, for templates written before
+    /// escaping became the default (which output the page with {{ body }})
+    #[clap(long, action)]
+    unescaped_templates: bool,
+
+    /// remove html tags and attributes that aren't allowlisted, and links
+    /// like javascript:, from markdown (for contributed content)
+    #[clap(long, action)]
+    sanitize: bool,
+
+    /// continue past files that fail to read or render, then report all of
+    /// the errors (still exiting with an error)
+    #[clap(long, action)]
+    keep_going: bool,
 }
 
 fn process_files(cli: Cli) -> Result<()> {
     println!("processing source files from:\t{}", &cli.inpath);
-    let mut web = Web::new(&cli.inpath, &cli.outpath, &cli.templatedir)?;
+    let config = Config {
+        search_inverted_index: cli.search_inverted_index,
+        include_drafts: cli.drafts,
+        include_future: cli.future,
+        permalink: cli.permalink,
+        strict_links: cli.strict_links,
+        heading_anchors: cli.heading_anchors,
+        markdown_extensions: MarkdownExtensions::from_names(&cli.markdown_extensions)?,
+        highlight_mode: cli.highlight,
+        highlight_theme: cli.highlight_theme,
+        image_widths: cli.image_widths,
+        image_webp: cli.webp,
+        image_sizes: cli.image_sizes,
+        cache_dir: cli.cache_dir,
+        strip_metadata: cli.strip_metadata,
+        transcripts: cli.transcripts,
+        fontawesome_kit: cli.fontawesome_kit,
+        unescaped_templates: cli.unescaped_templates,
+        sanitize: cli.sanitize,
+        keep_going: cli.keep_going,
+    };
+    let mut web = Web::new(&cli.inpath, &cli.outpath, &cli.templatedir, config)?;
     if cli.book {
         web.gen_book()?;
         println!("book created!");
@@ -39,12 +150,16 @@ fn process_files(cli: Cli) -> Result<()> {
     Ok(())
 }
 
-fn main() {
+fn main() -> ExitCode {
     pretty_env_logger::init();
     let cli = Cli::parse();
 
     if let Err(e) = process_files(cli) {
-        println!("Erorr processing files: {:#?}", e);
+        eprintln!("error: {}", e);
+        for cause in e.chain().skip(1) {
+            eprintln!("  caused by: {}", cause);
+        }
+        return ExitCode::FAILURE;
     }
-
+    ExitCode::SUCCESS
 }
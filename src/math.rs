@@ -0,0 +1,522 @@
+use crate::util::escape_html;
+
+// Math in markdown is written as `$x^2$` (inline) or `$$\sum_i x_i$$` (display),
+// found by `preprocess` before markdown is parsed and rendered here to MathML.
+
+// find the closing `$` of inline math starting at `start` (just after the opening `$`)
+pub fn find_inline_end(markdown: &str, start: usize) -> Option<usize> {
+    let rest = &markdown[start..];
+    // opening `$` must be followed by a non-space that isn't a digit (a price
+    // like $5), and can't follow a letter, digit or `\`
+    if rest.starts_with(|c: char| c.is_whitespace() || c.is_ascii_digit()) {
+        return None;
+    }
+    let before = markdown[..start.saturating_sub(1)].chars().next_back();
+    if matches!(before, Some(c) if c.is_alphanumeric() || c == '\\') {
+        return None;
+    }
+    let mut prev: Option<char> = None;
+    for (i, c) in rest.char_indices() {
+        match c {
+            // no paragraph breaks in inline math
+            '\n' if rest[i + 1..].split('\n').next().is_some_and(|line| line.trim().is_empty()) => return None,
+            '$' if prev != Some('\\') && i > 0 => {
+                let before_space = prev.filter(|c| !c.is_whitespace()).is_none();
+                let after_digit = rest[i + 1..].starts_with(|c: char| c.is_ascii_digit());
+                if !before_space && !after_digit {
+                    return Some(start + i);
+                }
+            }
+            _ => {}
+        }
+        prev = Some(c);
+    }
+    None
+}
+
+// LaTeX to MathML, covering the commonly used subset of TeX math:
+// letters, numbers and operators, `^` and `_`, `{}` groups, \frac, \sqrt,
+// greek letters and symbols, function names, accents, font styles,
+// \text, \left/\right, spacing and matrix-like environments.
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Command(String),
+    Char(char),
+    Number(String),
+    Text(String),
+    Open,
+    Close,
+    Sup,
+    Sub,
+    Align,
+    NewRow,
+}
+
+fn tokenize(tex: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = tex.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        i += 1;
+        match c {
+            c if c.is_whitespace() => {}
+            '{' => tokens.push(Token::Open),
+            '}' => tokens.push(Token::Close),
+            '^' => tokens.push(Token::Sup),
+            '_' => tokens.push(Token::Sub),
+            '&' => tokens.push(Token::Align),
+            '0'..='9' | '.' if c != '.' || chars.get(i).is_some_and(char::is_ascii_digit) => {
+                let mut number = c.to_string();
+                while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                    number.push(chars[i]);
+                    i += 1;
+                }
+                tokens.push(Token::Number(number));
+            }
+            '\\' => {
+                let next = match chars.get(i) {
+                    Some(next) => *next,
+                    None => return Err("trailing \\".into()),
+                };
+                i += 1;
+                if next == '\\' {
+                    tokens.push(Token::NewRow);
+                } else if next.is_ascii_alphabetic() {
+                    let mut name = next.to_string();
+                    while i < chars.len() && chars[i].is_ascii_alphabetic() {
+                        name.push(chars[i]);
+                        i += 1;
+                    }
+                    if matches!(name.as_str(), "text" | "textrm" | "mbox" | "textit" | "textbf") {
+                        // text arguments are read verbatim
+                        while i < chars.len() && chars[i].is_whitespace() {
+                            i += 1;
+                        }
+                        if chars.get(i) != Some(&'{') {
+                            return Err(format!("\\{} needs a {{...}} argument", name));
+                        }
+                        let mut depth = 0;
+                        let mut text = String::new();
+                        loop {
+                            match chars.get(i) {
+                                None => return Err(format!("unclosed \\{}{{", name)),
+                                Some('{') => depth += 1,
+                                Some('}') => {
+                                    depth -= 1;
+                                    if depth == 0 {
+                                        i += 1;
+                                        break;
+                                    }
+                                }
+                                _ => {}
+                            }
+                            if depth > 1 || chars[i] != '{' {
+                                text.push(chars[i]);
+                            }
+                            i += 1;
+                        }
+                        tokens.push(Token::Text(text));
+                    } else {
+                        tokens.push(Token::Command(name));
+                    }
+                } else {
+                    // control symbols like \{ \, \;
+                    tokens.push(Token::Command(next.to_string()));
+                }
+            }
+            c => tokens.push(Token::Char(c)),
+        }
+    }
+    Ok(tokens)
+}
+
+fn greek(name: &str) -> Option<char> {
+    Some(match name {
+        "alpha" => 'α', "beta" => 'β', "gamma" => 'γ', "delta" => 'δ',
+        "epsilon" => 'ϵ', "varepsilon" => 'ε', "zeta" => 'ζ', "eta" => 'η',
+        "theta" => 'θ', "vartheta" => 'ϑ', "iota" => 'ι', "kappa" => 'κ',
+        "lambda" => 'λ', "mu" => 'μ', "nu" => 'ν', "xi" => 'ξ', "pi" => 'π',
+        "varpi" => 'ϖ', "rho" => 'ρ', "varrho" => 'ϱ', "sigma" => 'σ',
+        "varsigma" => 'ς', "tau" => 'τ', "upsilon" => 'υ', "phi" => 'ϕ',
+        "varphi" => 'φ', "chi" => 'χ', "psi" => 'ψ', "omega" => 'ω',
+        "Gamma" => 'Γ', "Delta" => 'Δ', "Theta" => 'Θ', "Lambda" => 'Λ',
+        "Xi" => 'Ξ', "Pi" => 'Π', "Sigma" => 'Σ', "Upsilon" => 'Υ',
+        "Phi" => 'Φ', "Psi" => 'Ψ', "Omega" => 'Ω',
+        "infty" => '∞', "partial" => '∂', "nabla" => '∇', "ell" => 'ℓ',
+        "hbar" => 'ℏ', "emptyset" => '∅', "aleph" => 'ℵ',
+        _ => return None,
+    })
+}
+
+fn operator(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "pm" => "±", "mp" => "∓", "times" => "×", "div" => "÷", "cdot" => "⋅",
+        "ast" => "∗", "star" => "⋆", "circ" => "∘", "bullet" => "∙",
+        "le" | "leq" => "≤", "ge" | "geq" => "≥", "ne" | "neq" => "≠",
+        "approx" => "≈", "equiv" => "≡", "sim" => "∼", "simeq" => "≃",
+        "cong" => "≅", "propto" => "∝", "ll" => "≪", "gg" => "≫",
+        "in" => "∈", "notin" => "∉", "ni" => "∋", "subset" => "⊂",
+        "supset" => "⊃", "subseteq" => "⊆", "supseteq" => "⊇",
+        "cup" => "∪", "cap" => "∩", "setminus" => "∖", "wedge" | "land" => "∧",
+        "vee" | "lor" => "∨", "neg" | "lnot" => "¬", "forall" => "∀",
+        "exists" => "∃", "to" | "rightarrow" => "→", "leftarrow" | "gets" => "←",
+        "leftrightarrow" => "↔", "Rightarrow" | "implies" => "⇒",
+        "Leftarrow" => "⇐", "Leftrightarrow" | "iff" => "⇔", "mapsto" => "↦",
+        "mid" => "∣", "parallel" => "∥", "perp" => "⊥", "angle" => "∠",
+        "cdots" => "⋯", "ldots" | "dots" => "…", "vdots" => "⋮", "ddots" => "⋱",
+        "langle" => "⟨", "rangle" => "⟩", "lceil" => "⌈", "rceil" => "⌉",
+        "lfloor" => "⌊", "rfloor" => "⌋", "vert" => "|", "Vert" => "‖",
+        "{" => "{", "}" => "}", "|" => "‖", "%" => "%", "$" => "$", "#" => "#",
+        "&" => "&", "_" => "_",
+        _ => return None,
+    })
+}
+
+// operators that take limits below and above in display math
+fn big_operator(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "sum" => "∑", "prod" => "∏", "coprod" => "∐", "int" => "∫",
+        "iint" => "∬", "iiint" => "∭", "oint" => "∮", "bigcup" => "⋃",
+        "bigcap" => "⋂", "bigvee" => "⋁", "bigwedge" => "⋀",
+        _ => return None,
+    })
+}
+
+fn function_name(name: &str) -> bool {
+    matches!(
+        name,
+        "sin" | "cos" | "tan" | "cot" | "sec" | "csc" | "arcsin" | "arccos" | "arctan"
+            | "sinh" | "cosh" | "tanh" | "log" | "ln" | "lg" | "exp" | "det" | "dim"
+            | "ker" | "deg" | "gcd" | "arg" | "hom" | "Pr" | "lim" | "liminf"
+            | "limsup" | "max" | "min" | "sup" | "inf"
+    )
+}
+
+fn accent(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "hat" | "widehat" => "^", "bar" | "overline" => "¯", "vec" => "→",
+        "dot" => "˙", "ddot" => "¨", "tilde" | "widetilde" => "~",
+        _ => return None,
+    })
+}
+
+fn font_variant(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "mathbf" | "boldsymbol" => "bold", "mathrm" => "normal",
+        "mathit" => "italic", "mathbb" => "double-struck",
+        "mathcal" => "script", "mathfrak" => "fraktur", "mathsf" => "sans-serif",
+        "mathtt" => "monospace",
+        _ => return None,
+    })
+}
+
+fn space(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "," => "0.1667em", ":" | ">" => "0.2222em", ";" => "0.2778em",
+        "!" => "-0.1667em", " " => "0.25em", "quad" => "1em", "qquad" => "2em",
+        _ => return None,
+    })
+}
+
+fn wrap_row(items: Vec<String>) -> String {
+    if items.len() == 1 {
+        items.into_iter().next().unwrap_or_default()
+    } else {
+        format!("<mrow>{}</mrow>", items.concat())
+    }
+}
+
+struct TexParser {
+    tokens: Vec<Token>,
+    pos: usize,
+    display: bool,
+}
+
+impl TexParser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    // items up to (not including) a `}`, `&`, `\\`, \right or \end
+    fn parse_row(&mut self) -> Result<Vec<String>, String> {
+        let mut items = Vec::new();
+        loop {
+            match self.peek() {
+                None | Some(Token::Close) | Some(Token::Align) | Some(Token::NewRow) => break,
+                Some(Token::Command(name)) if name == "right" || name == "end" => break,
+                _ => {}
+            }
+            let (base, limits) = self.parse_atom()?;
+            items.push(self.parse_scripts(base, limits)?);
+        }
+        Ok(items)
+    }
+
+    fn parse_scripts(&mut self, base: String, limits: bool) -> Result<String, String> {
+        let mut sub = None;
+        let mut sup = None;
+        loop {
+            match self.peek() {
+                Some(Token::Sub) if sub.is_none() => {
+                    self.next();
+                    sub = Some(self.parse_argument()?);
+                }
+                Some(Token::Sup) if sup.is_none() => {
+                    self.next();
+                    sup = Some(self.parse_argument()?);
+                }
+                Some(Token::Char('\'')) => {
+                    // prime
+                    self.next();
+                    let prime = sup.take().unwrap_or_default();
+                    sup = Some(wrap_row(vec![prime, "<mo>′</mo>".into()]));
+                }
+                _ => break,
+            }
+        }
+        let (under, over, both) = if limits && self.display {
+            ("munder", "mover", "munderover")
+        } else {
+            ("msub", "msup", "msubsup")
+        };
+        Ok(match (sub, sup) {
+            (None, None) => base,
+            (Some(sub), None) => format!("<{0}>{1}{2}</{0}>", under, base, sub),
+            (None, Some(sup)) => format!("<{0}>{1}{2}</{0}>", over, base, sup),
+            (Some(sub), Some(sup)) => format!("<{0}>{1}{2}{3}</{0}>", both, base, sub, sup),
+        })
+    }
+
+    // a single atom or `{...}` group, as an argument to a command or script
+    fn parse_argument(&mut self) -> Result<String, String> {
+        match self.peek() {
+            Some(Token::Open) => {
+                let (group, _) = self.parse_atom()?;
+                Ok(group)
+            }
+            Some(_) => Ok(self.parse_atom()?.0),
+            None => Err("missing argument".into()),
+        }
+    }
+
+    fn parse_group_contents(&mut self) -> Result<String, String> {
+        let items = self.parse_row()?;
+        match self.next() {
+            Some(Token::Close) => Ok(format!("<mrow>{}</mrow>", items.concat())),
+            _ => Err("missing }".into()),
+        }
+    }
+
+    fn parse_delimiter(&mut self) -> Result<String, String> {
+        let delimiter = match self.next() {
+            Some(Token::Char('.')) => String::new(),
+            Some(Token::Char(c)) => c.to_string(),
+            Some(Token::Command(name)) => match operator(&name) {
+                Some(op) => op.to_string(),
+                None => return Err(format!("unknown delimiter \\{}", name)),
+            },
+            _ => return Err("missing delimiter".into()),
+        };
+        Ok(format!("<mo stretchy=\"true\">{}</mo>", escape_html(&delimiter)))
+    }
+
+    fn parse_environment(&mut self) -> Result<String, String> {
+        let name = self.parse_name()?;
+        let (open, close) = match name.as_str() {
+            "matrix" | "aligned" | "align" | "align*" | "array" | "gathered" => ("", ""),
+            "pmatrix" => ("(", ")"),
+            "bmatrix" => ("[", "]"),
+            "Bmatrix" => ("{", "}"),
+            "vmatrix" => ("|", "|"),
+            "Vmatrix" => ("‖", "‖"),
+            "cases" => ("{", ""),
+            _ => return Err(format!("unknown environment {}", name)),
+        };
+        if name == "array" && self.peek() == Some(&Token::Open) {
+            self.parse_name()?; // column spec
+        }
+        let mut rows = Vec::new();
+        let mut row = Vec::new();
+        loop {
+            row.push(format!("<mtd>{}</mtd>", wrap_row(self.parse_row()?)));
+            match self.next() {
+                Some(Token::Align) => {}
+                Some(Token::NewRow) => rows.push(format!("<mtr>{}</mtr>", std::mem::take(&mut row).concat())),
+                Some(Token::Command(end)) if end == "end" => {
+                    if self.parse_name()? != name {
+                        return Err(format!("\\begin{{{}}} ended by a different \\end", name));
+                    }
+                    break;
+                }
+                _ => return Err(format!("missing \\end{{{}}}", name)),
+            }
+        }
+        if row.iter().any(|cell| cell != "<mtd><mrow></mrow></mtd>") {
+            rows.push(format!("<mtr>{}</mtr>", row.concat()));
+        }
+        let table = format!("<mtable>{}</mtable>", rows.concat());
+        if open.is_empty() && close.is_empty() {
+            return Ok(table);
+        }
+        Ok(format!(
+            "<mrow><mo>{}</mo>{}<mo>{}</mo></mrow>",
+            escape_html(open),
+            table,
+            escape_html(close)
+        ))
+    }
+
+    // `{name}` as used by \begin and \end
+    fn parse_name(&mut self) -> Result<String, String> {
+        if self.next() != Some(Token::Open) {
+            return Err("expected {".into());
+        }
+        let mut name = String::new();
+        loop {
+            match self.next() {
+                Some(Token::Close) => return Ok(name),
+                Some(Token::Char(c)) => name.push(c),
+                Some(Token::Number(n)) => name.push_str(&n),
+                _ => return Err("unclosed {".into()),
+            }
+        }
+    }
+
+    // returns MathML and whether the atom takes limits (like \sum)
+    fn parse_atom(&mut self) -> Result<(String, bool), String> {
+        let token = match self.next() {
+            Some(token) => token,
+            None => return Err("unexpected end of math".into()),
+        };
+        let mathml = match token {
+            Token::Open => self.parse_group_contents()?,
+            Token::Close => return Err("unexpected }".into()),
+            Token::Number(n) => format!("<mn>{}</mn>", n),
+            Token::Text(text) => format!("<mtext>{}</mtext>", escape_html(&text)),
+            Token::Sup | Token::Sub => {
+                // script without a base
+                self.pos -= 1;
+                "<mrow></mrow>".to_string()
+            }
+            Token::Align | Token::NewRow => return Err("& or \\\\ outside of an environment".into()),
+            Token::Char(c) if c.is_alphabetic() => format!("<mi>{}</mi>", c),
+            Token::Char(c) => format!("<mo>{}</mo>", escape_html(&c.to_string())),
+            Token::Command(name) => {
+                if let Some(c) = greek(&name) {
+                    format!("<mi>{}</mi>", c)
+                } else if let Some(op) = big_operator(&name) {
+                    return Ok((format!("<mo>{}</mo>", op), true));
+                } else if let Some(op) = operator(&name) {
+                    format!("<mo>{}</mo>", escape_html(op))
+                } else if function_name(&name) {
+                    let limits = matches!(name.as_str(), "lim" | "liminf" | "limsup" | "max" | "min" | "sup" | "inf");
+                    return Ok((format!("<mi>{}</mi>", name), limits));
+                } else if let Some(width) = space(&name) {
+                    format!("<mspace width=\"{}\"/>", width)
+                } else if let Some(mark) = accent(&name) {
+                    let base = self.parse_argument()?;
+                    format!("<mover accent=\"true\">{}<mo>{}</mo></mover>", base, mark)
+                } else if let Some(variant) = font_variant(&name) {
+                    let arg = self.parse_argument()?;
+                    format!("<mstyle mathvariant=\"{}\">{}</mstyle>", variant, arg)
+                } else {
+                    match name.as_str() {
+                        "frac" | "dfrac" | "tfrac" => {
+                            let numerator = self.parse_argument()?;
+                            let denominator = self.parse_argument()?;
+                            format!("<mfrac>{}{}</mfrac>", numerator, denominator)
+                        }
+                        "binom" => {
+                            let n = self.parse_argument()?;
+                            let k = self.parse_argument()?;
+                            format!(
+                                "<mrow><mo>(</mo><mfrac linethickness=\"0\">{}{}</mfrac><mo>)</mo></mrow>",
+                                n, k
+                            )
+                        }
+                        "sqrt" => {
+                            if self.peek() == Some(&Token::Char('[')) {
+                                self.next();
+                                let mut index = Vec::new();
+                                while !matches!(self.peek(), Some(Token::Char(']')) | None) {
+                                    // scripts are parsed with their base, like in a row
+                                    let (base, limits) = self.parse_atom()?;
+                                    index.push(self.parse_scripts(base, limits)?);
+                                }
+                                self.next();
+                                let radicand = self.parse_argument()?;
+                                format!("<mroot>{}{}</mroot>", radicand, wrap_row(index))
+                            } else {
+                                format!("<msqrt>{}</msqrt>", self.parse_argument()?)
+                            }
+                        }
+                        "left" => {
+                            let open = self.parse_delimiter()?;
+                            let inner = self.parse_row()?;
+                            match self.next() {
+                                Some(Token::Command(right)) if right == "right" => {}
+                                _ => return Err("\\left without \\right".into()),
+                            }
+                            let close = self.parse_delimiter()?;
+                            format!("<mrow>{}{}{}</mrow>", open, inner.concat(), close)
+                        }
+                        "right" => return Err("\\right without \\left".into()),
+                        "begin" => self.parse_environment()?,
+                        "end" => return Err("\\end without \\begin".into()),
+                        "displaystyle" | "textstyle" | "limits" | "nolimits" => String::new(),
+                        _ => return Err(format!("unknown command \\{}", name)),
+                    }
+                }
+            }
+        };
+        Ok((mathml, false))
+    }
+}
+
+// render TeX math as a MathML `<math>` element, with the TeX source kept
+// as an annotation; errors describe the first unsupported or invalid input
+pub fn latex_to_mathml(tex: &str, display: bool) -> Result<String, String> {
+    let mut parser = TexParser {
+        tokens: tokenize(tex)?,
+        pos: 0,
+        display,
+    };
+    let items = parser.parse_row()?;
+    if let Some(token) = parser.peek() {
+        return Err(format!("unexpected {:?}", token));
+    }
+    let display_attr = if display { " display=\"block\"" } else { "" };
+    Ok(format!(
+        "<math xmlns=\"http://www.w3.org/1998/Math/MathML\"{}><semantics><mrow>{}</mrow><annotation encoding=\"application/x-tex\">{}</annotation></semantics></math>",
+        display_attr,
+        items.concat(),
+        escape_html(tex.trim())
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_latex_to_mathml() {
+        let mathml = latex_to_mathml(r"\frac{a_1}{\sqrt{x^2}}", false).expect("render");
+        assert!(mathml.contains("<mfrac><mrow><msub><mi>a</mi><mn>1</mn></msub></mrow><mrow><msqrt><mrow><msup><mi>x</mi><mn>2</mn></msup></mrow></msqrt></mrow></mfrac>"));
+        assert!(latex_to_mathml(r"\unknown{x}", false).is_err());
+        assert!(latex_to_mathml(r"{x", false).is_err());
+        let mathml = latex_to_mathml(r"\sqrt[n^2]{x}", false).expect("render");
+        assert!(mathml.contains("<mroot><mrow><mi>x</mi></mrow><msup><mi>n</mi><mn>2</mn></msup></mroot>"), "{}", mathml);
+        // a script without a base in the index doesn't loop forever
+        assert!(latex_to_mathml(r"\sqrt[^]{x}", false).is_err());
+        assert!(latex_to_mathml(r"\sqrt[_]{x}", false).is_err());
+        assert!(latex_to_mathml(r"\sqrt[^2]{x}", false).is_ok());
+    }
+}
@@ -0,0 +1,341 @@
+use crate::util::escape_html;
+use serde::Serialize;
+use std::fs::File;
+use std::path::Path;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::{MetadataOptions, MetadataRevision, StandardTagKey, StandardVisualKey};
+use symphonia::core::probe::Hint;
+
+// Links to audio files become an `<audio>` player and links to video files
+// a `<video>` player, each falling back to the link itself. A video uses
+// files next to it with the same name: an image (`demo.jpg`, `.png` or
+// `.webp`) as its poster, which also gives the player its size, and
+// `demo.vtt` as its captions.
+// Audio and video both get captions from a `.vtt` file next to them, or an
+// `.srt` file, which is published converted to WebVTT. With `--transcripts`,
+// the caption text is also shown as a collapsible transcript below the player.
+// Players are rendered with the `partials/audio.hbs` and `partials/video.hbs`
+// templates; audio also gets the file's title, artist, album, duration and
+// artwork, read from its ID3, Vorbis comment, RIFF INFO or MP4 tags.
+
+pub const AUDIO_PARTIAL: &str = "partials/audio";
+pub const VIDEO_PARTIAL: &str = "partials/video";
+
+// extensions of images that can be a video's poster, in order of preference
+pub const POSTER_EXTENSIONS: [&str; 4] = ["jpg", "jpeg", "png", "webp"];
+// caption files, in order of preference
+pub const CAPTIONS_EXTENSIONS: [&str; 2] = ["vtt", "srt"];
+pub const SRT_EXTENSION: &str = "srt";
+
+// a link to an audio or video file, with urls relative to the page
+#[derive(Debug, Default, Serialize)]
+pub struct MediaLink {
+    pub url: String,
+    pub title: String,
+    pub mimetype: String,
+    // plain text of the link, shown in the fallback
+    pub link_text: String,
+    // WebVTT captions
+    pub captions: Option<String>,
+}
+
+// what `partials/audio.hbs` gets to render an audio player
+pub fn audio_vars(audio: &MediaLink, metadata: &AudioMetadata) -> serde_json::Result<serde_json::Value> {
+    let mut vars = serde_json::to_value(audio)?;
+    vars["metadata"] = serde_json::to_value(metadata)?;
+    Ok(vars)
+}
+
+// details read from an audio file, each only when present
+#[derive(Debug, Default, Serialize)]
+pub struct AudioMetadata {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    // whole seconds, and as text like "3:07" or "1:02:03"
+    pub duration: Option<u64>,
+    pub duration_text: Option<String>,
+    // url of the embedded artwork, once written out
+    pub artwork: Option<String>,
+    // embedded artwork with its file extension
+    #[serde(skip)]
+    pub artwork_data: Option<(Box<[u8]>, &'static str)>,
+}
+
+fn duration_text(seconds: u64) -> String {
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        format!("{}:{:02}", minutes, seconds)
+    }
+}
+
+impl AudioMetadata {
+    // fill in anything still missing from one set of tags
+    fn add_tags(&mut self, revision: &MetadataRevision) {
+        for tag in revision.tags() {
+            let field = match tag.std_key {
+                Some(StandardTagKey::TrackTitle) => &mut self.title,
+                Some(StandardTagKey::Artist) => &mut self.artist,
+                Some(StandardTagKey::Album) => &mut self.album,
+                _ => continue,
+            };
+            // RIFF INFO values can keep their NUL padding
+            let value = tag.value.to_string();
+            let value = value.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+            if field.is_none() && !value.is_empty() {
+                *field = Some(value.to_string());
+            }
+        }
+        if self.artwork_data.is_none() {
+            let visuals = revision.visuals();
+            let artwork = visuals
+                .iter()
+                .find(|visual| visual.usage == Some(StandardVisualKey::FrontCover))
+                .or(visuals.first());
+            if let Some(artwork) = artwork {
+                let ext = match artwork.media_type.as_str() {
+                    "image/jpeg" | "image/jpg" => Some("jpg"),
+                    "image/png" => Some("png"),
+                    "image/gif" => Some("gif"),
+                    "image/webp" => Some("webp"),
+                    _ => None,
+                };
+                self.artwork_data = ext.map(|ext| (artwork.data.clone(), ext));
+            }
+        }
+    }
+}
+
+pub fn read_audio_metadata(path: &Path) -> anyhow::Result<AudioMetadata> {
+    let source = MediaSourceStream::new(Box::new(File::open(path)?), Default::default());
+    let mut hint = Hint::new();
+    if let Some(ext) = path.extension() {
+        hint.with_extension(&ext.to_string_lossy());
+    }
+    let mut probed = symphonia::default::get_probe().format(
+        &hint,
+        source,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    )?;
+    let mut metadata = AudioMetadata::default();
+    // tags inside the container, then any before it (like ID3 in an mp3)
+    if let Some(revision) = probed.format.metadata().current() {
+        metadata.add_tags(revision);
+    }
+    if let Some(revision) = probed.metadata.get().as_ref().and_then(|log| log.current()) {
+        metadata.add_tags(revision);
+    }
+    if let Some(track) = probed.format.default_track() {
+        let params = &track.codec_params;
+        if let (Some(frames), Some(time_base)) = (params.n_frames, params.time_base) {
+            let seconds = time_base.calc_time(frames).seconds;
+            metadata.duration = Some(seconds);
+            metadata.duration_text = Some(duration_text(seconds));
+        }
+    }
+    Ok(metadata)
+}
+
+// a video's optional extras, with urls relative to the page
+#[derive(Debug, Default, Serialize)]
+pub struct VideoExtras {
+    pub poster: Option<String>,
+    // size of the poster
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+}
+
+// what `partials/video.hbs` gets to render a video player
+pub fn video_vars(video: &MediaLink, extras: &VideoExtras) -> serde_json::Result<serde_json::Value> {
+    let mut vars = serde_json::to_value(video)?;
+    if let (Some(vars), serde_json::Value::Object(extras)) = (vars.as_object_mut(), serde_json::to_value(extras)?) {
+        vars.extend(extras);
+    }
+    Ok(vars)
+}
+
+// SubRip captions as WebVTT: a header, and `.` instead of `,` in timings
+pub fn srt_to_vtt(srt: &str) -> String {
+    let mut vtt = String::from("WEBVTT\n\n");
+    for line in srt.trim_start_matches('\u{feff}').lines() {
+        if line.contains("-->") {
+            vtt.push_str(&line.replace(',', "."));
+        } else {
+            vtt.push_str(line);
+        }
+        vtt.push('\n');
+    }
+    vtt
+}
+
+// the text of each cue in WebVTT or SubRip captions, without markup
+pub fn cue_texts(captions: &str) -> Vec<String> {
+    let captions = captions.replace("\r\n", "\n");
+    let mut cues = Vec::new();
+    for block in captions.split("\n\n") {
+        // the timing line comes after an optional cue identifier
+        let mut lines = block.lines().skip_while(|line| !line.contains("-->"));
+        if lines.next().is_none() {
+            continue; // header, NOTE, STYLE or REGION block
+        }
+        let text: Vec<String> = lines
+            .map(|line| strip_cue_markup(line).trim().to_string())
+            .filter(|line| !line.is_empty())
+            .collect();
+        if !text.is_empty() {
+            cues.push(text.join(" "));
+        }
+    }
+    cues
+}
+
+// remove tags like `<v Speaker>` or `<i>` and decode entities
+fn strip_cue_markup(line: &str) -> String {
+    let mut text = String::new();
+    let mut in_tag = false;
+    for c in line.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => in_tag = false,
+            c if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&nbsp;", "\u{a0}")
+        .replace("&amp;", "&")
+}
+
+pub fn transcript_html(cues: &[String]) -> String {
+    let mut html = String::from("<details class=\"transcript\">\n<summary>Transcript</summary>\n");
+    for cue in cues {
+        html.push_str(&format!("<p>{}</p>\n", escape_html(cue)));
+    }
+    html.push_str("</details>\n");
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_duration_text() {
+        assert_eq!(duration_text(7), "0:07");
+        assert_eq!(duration_text(187), "3:07");
+        assert_eq!(duration_text(3723), "1:02:03");
+    }
+
+    // a 3 second silent wav file, with a RIFF INFO chunk of `tags`
+    fn wav_file(name: &str, tags: &[(&[u8; 4], &str)]) -> std::path::PathBuf {
+        let chunk = |id: &[u8], data: &[u8]| {
+            let mut chunk = id.to_vec();
+            chunk.extend((data.len() as u32).to_le_bytes());
+            chunk.extend(data);
+            if data.len() % 2 == 1 {
+                chunk.push(0);
+            }
+            chunk
+        };
+        let (rate, channels, bits) = (8000u32, 1u16, 16u16);
+        let mut fmt = 1u16.to_le_bytes().to_vec(); // PCM
+        fmt.extend(channels.to_le_bytes());
+        fmt.extend(rate.to_le_bytes());
+        fmt.extend((rate * bits as u32 / 8).to_le_bytes());
+        fmt.extend((bits / 8).to_le_bytes());
+        fmt.extend(bits.to_le_bytes());
+        let mut body = b"WAVE".to_vec();
+        body.extend(chunk(b"fmt ", &fmt));
+        if !tags.is_empty() {
+            let mut info = b"INFO".to_vec();
+            for (id, value) in tags {
+                info.extend(chunk(*id, format!("{}\0", value).as_bytes()));
+            }
+            body.extend(chunk(b"LIST", &info));
+        }
+        body.extend(chunk(b"data", &vec![0; rate as usize * 2 * 3]));
+        let path = std::env::temp_dir().join("webgenr-tests").join(name);
+        std::fs::create_dir_all(path.parent().expect("parent")).expect("create dir");
+        std::fs::write(&path, chunk(b"RIFF", &body)).expect("write wav");
+        path
+    }
+
+    #[test]
+    fn test_audio_metadata() {
+        let path = wav_file("tagged.wav", &[(b"INAM", "Silence"), (b"IART", "Nobody")]);
+        let metadata = read_audio_metadata(&path).expect("metadata");
+        assert_eq!(metadata.title.as_deref(), Some("Silence"));
+        assert_eq!(metadata.artist.as_deref(), Some("Nobody"));
+        assert_eq!(metadata.album, None);
+        assert_eq!(metadata.duration, Some(3));
+        assert_eq!(metadata.duration_text.as_deref(), Some("0:03"));
+
+        // without tags the player shows only the duration, and the link text
+        // as the fallback
+        let metadata = read_audio_metadata(&wav_file("untagged.wav", &[])).expect("metadata");
+        assert_eq!((metadata.title.as_deref(), metadata.artist.as_deref()), (None, None));
+        assert_eq!(metadata.duration_text.as_deref(), Some("0:03"));
+        let audio = MediaLink {
+            url: "untagged.wav".into(),
+            mimetype: "audio/wav".into(),
+            link_text: "Untagged".into(),
+            ..Default::default()
+        };
+        let mut handlebars = handlebars::Handlebars::new();
+        handlebars.register_helper(crate::icons::ICON_HELPER, Box::new(crate::icons::icon_helper));
+        handlebars
+            .register_template_string(AUDIO_PARTIAL, include_str!("../templates/partials/audio.hbs"))
+            .expect("template");
+        let html = handlebars.render(AUDIO_PARTIAL, &audio_vars(&audio, &metadata).expect("vars")).expect("render");
+        assert!(!html.contains("audio-title") && !html.contains("audio-artist"), "{}", html);
+        assert!(html.contains("<span class=\"audio-duration\">0:03</span>"), "{}", html);
+        assert!(html.contains("Untagged</a>"), "{}", html);
+    }
+
+    #[test]
+    fn test_video_html() {
+        let video = MediaLink {
+            url: "demo.mp4".into(),
+            mimetype: "video/mp4".into(),
+            link_text: "Demo".into(),
+            captions: Some("demo.vtt".into()),
+            ..Default::default()
+        };
+        let extras = VideoExtras {
+            poster: Some("demo.jpg".into()),
+            width: Some(640),
+            height: Some(360),
+        };
+        let mut handlebars = handlebars::Handlebars::new();
+        handlebars
+            .register_template_string(VIDEO_PARTIAL, include_str!("../templates/partials/video.hbs"))
+            .expect("template");
+        let vars = video_vars(&video, &extras).expect("vars");
+        assert_eq!(
+            handlebars.render(VIDEO_PARTIAL, &vars).expect("render").trim_end(),
+            "<video controls preload=\"metadata\" width=\"640\" height=\"360\" poster=\"demo.jpg\">\
+             <source src=\"demo.mp4\" type=\"video/mp4\" />\
+             <track kind=\"captions\" src=\"demo.vtt\" label=\"Captions\" default=\"default\" />\
+             Your browser does not support the video element. <a href=\"demo.mp4\" class=\"video\">Demo</a></video>"
+        );
+    }
+
+    #[test]
+    fn test_captions() {
+        let srt = "1\r\n00:00:01,000 --> 00:00:02,500\r\nHello &amp; <i>welcome</i>\r\n\r\n\
+                   2\r\n00:00:03,000 --> 00:00:04,000\r\nTwo\r\nlines\r\n";
+        let vtt = srt_to_vtt(srt);
+        assert_eq!(
+            vtt,
+            "WEBVTT\n\n1\n00:00:01.000 --> 00:00:02.500\nHello &amp; <i>welcome</i>\n\n\
+             2\n00:00:03.000 --> 00:00:04.000\nTwo\nlines\n"
+        );
+        assert_eq!(cue_texts(&vtt), vec!["Hello & welcome", "Two lines"]);
+        assert_eq!(cue_texts(srt), cue_texts(&vtt));
+    }
+}
@@ -0,0 +1,162 @@
+use crate::images::encode;
+use anyhow::{bail, Context};
+use image::metadata::Orientation;
+use image::{DynamicImage, ImageDecoder, ImageReader};
+use std::io::Cursor;
+use std::path::Path;
+
+// Removing EXIF, XMP and similar metadata (camera details, GPS position)
+// from published images. Files are rewritten container by container, so
+// pixels are untouched, except that an image with an EXIF orientation is
+// decoded, rotated and re-encoded, since the orientation goes with the EXIF.
+
+pub fn is_strippable_image(path: &Path) -> bool {
+    let ext = path.extension().map(|ext| ext.to_string_lossy().to_lowercase());
+    matches!(ext.as_deref(), Some("jpg") | Some("jpeg") | Some("png") | Some("webp"))
+}
+
+// image bytes without metadata, with any EXIF orientation applied
+pub fn strip_metadata(bytes: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let reader = ImageReader::new(Cursor::new(bytes)).with_guessed_format()?;
+    let format = reader.format().context("unknown image format")?;
+    let mut decoder = reader.into_decoder()?;
+    let orientation = decoder.orientation()?;
+    if orientation != Orientation::NoTransforms {
+        let mut image = DynamicImage::from_decoder(decoder)?;
+        image.apply_orientation(orientation);
+        let mut out = Vec::new();
+        encode(&image, format, Cursor::new(&mut out))?;
+        return Ok(out);
+    }
+    if bytes.starts_with(&[0xff, 0xd8]) {
+        strip_jpeg(bytes)
+    } else if bytes.starts_with(PNG_SIGNATURE) {
+        strip_png(bytes)
+    } else if bytes.starts_with(b"RIFF") && bytes.get(8..12) == Some(b"WEBP") {
+        strip_webp(bytes)
+    } else {
+        bail!("not a jpeg, png or webp image")
+    }
+}
+
+// drop APP1 (EXIF, XMP), APP13 (IPTC) and comment segments
+fn strip_jpeg(bytes: &[u8]) -> anyhow::Result<Vec<u8>> {
+    const APP1: u8 = 0xe1;
+    const APP13: u8 = 0xed;
+    const COM: u8 = 0xfe;
+    const SOS: u8 = 0xda;
+    let mut out = bytes[..2].to_vec();
+    let mut pos = 2;
+    loop {
+        if bytes.get(pos) != Some(&0xff) {
+            bail!("invalid jpeg segment at byte {}", pos);
+        }
+        let marker = *bytes.get(pos + 1).context("truncated jpeg")?;
+        if marker == 0xff {
+            pos += 1; // fill byte
+            continue;
+        }
+        if marker == SOS {
+            // compressed image data follows, up to the end of the file
+            out.extend_from_slice(&bytes[pos..]);
+            return Ok(out);
+        }
+        let len = match bytes.get(pos + 2..pos + 4) {
+            Some(len) => u16::from_be_bytes([len[0], len[1]]) as usize,
+            None => bail!("truncated jpeg"),
+        };
+        let end = pos + 2 + len;
+        if end > bytes.len() {
+            bail!("truncated jpeg");
+        }
+        if !matches!(marker, APP1 | APP13 | COM) {
+            out.extend_from_slice(&bytes[pos..end]);
+        }
+        pos = end;
+    }
+}
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+// drop EXIF, text (which holds XMP and other metadata) and time chunks
+fn strip_png(bytes: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut out = PNG_SIGNATURE.to_vec();
+    let mut pos = PNG_SIGNATURE.len();
+    while pos < bytes.len() {
+        let header = bytes.get(pos..pos + 8).context("truncated png")?;
+        let len = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let kind = &header[4..8];
+        let end = pos + 12 + len; // length, type, data and crc
+        if end > bytes.len() {
+            bail!("truncated png");
+        }
+        if !matches!(kind, b"eXIf" | b"tEXt" | b"zTXt" | b"iTXt" | b"tIME") {
+            out.extend_from_slice(&bytes[pos..end]);
+        }
+        pos = end;
+    }
+    Ok(out)
+}
+
+// drop EXIF and XMP chunks and clear their flags in the VP8X header
+fn strip_webp(bytes: &[u8]) -> anyhow::Result<Vec<u8>> {
+    const EXIF_FLAG: u8 = 0x08;
+    const XMP_FLAG: u8 = 0x04;
+    let mut out = bytes[..12].to_vec();
+    let mut pos = 12;
+    while pos < bytes.len() {
+        let header = bytes.get(pos..pos + 8).context("truncated webp")?;
+        let len = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        let end = (pos + 8 + len + len % 2).min(bytes.len()); // chunks are padded to even sizes
+        if pos + 8 + len > bytes.len() {
+            bail!("truncated webp");
+        }
+        match &header[..4] {
+            b"EXIF" | b"XMP " => {}
+            b"VP8X" => {
+                let start = out.len();
+                out.extend_from_slice(&bytes[pos..end]);
+                if let Some(flags) = out.get_mut(start + 8) {
+                    *flags &= !(EXIF_FLAG | XMP_FLAG);
+                }
+            }
+            _ => out.extend_from_slice(&bytes[pos..end]),
+        }
+        pos = end;
+    }
+    let riff_size = (out.len() - 8) as u32;
+    out[4..8].copy_from_slice(&riff_size.to_le_bytes());
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strip_jpeg_segments() {
+        let mut jpeg = vec![0xff, 0xd8];
+        jpeg.extend_from_slice(&[0xff, 0xe0, 0x00, 0x04, b'J', b'F']); // APP0, kept
+        jpeg.extend_from_slice(&[0xff, 0xe1, 0x00, 0x06, b'E', b'x', b'i', b'f']); // APP1, dropped
+        jpeg.extend_from_slice(&[0xff, 0xfe, 0x00, 0x03, b'!']); // comment, dropped
+        jpeg.extend_from_slice(&[0xff, 0xda, 0x00, 0x02, 1, 2, 3, 0xff, 0xd9]); // scan
+        assert_eq!(
+            strip_jpeg(&jpeg).expect("strip"),
+            vec![0xff, 0xd8, 0xff, 0xe0, 0x00, 0x04, b'J', b'F', 0xff, 0xda, 0x00, 0x02, 1, 2, 3, 0xff, 0xd9]
+        );
+    }
+
+    #[test]
+    fn test_strip_webp_chunks() {
+        let mut webp = b"RIFF\0\0\0\0WEBP".to_vec();
+        webp.extend_from_slice(b"VP8X\x0a\0\0\0\x0c\0\0\0\0\0\0\0\0\0");
+        webp.extend_from_slice(b"VP8L\x01\0\0\0\x2f\0"); // odd size, padded
+        webp.extend_from_slice(b"EXIF\x02\0\0\0ab");
+        webp.extend_from_slice(b"XMP \x01\0\0\0x\0");
+        let stripped = strip_webp(&webp).expect("strip");
+        assert_eq!(&stripped[..4], b"RIFF");
+        assert_eq!(u32::from_le_bytes([stripped[4], stripped[5], stripped[6], stripped[7]]) as usize, stripped.len() - 8);
+        assert_eq!(stripped[20], 0); // VP8X flags cleared
+        assert_eq!(stripped.len(), 12 + 18 + 10);
+    }
+}
@@ -0,0 +1,158 @@
+use crate::util::escape_html;
+use pulldown_cmark::escape::escape_href;
+
+// Playlists play a list of audio files with a single player, for a page
+// with front matter listing the files, or naming a folder of them:
+//   playlist: [01-intro.mp3, 02-theme.mp3]
+//   playlist: album/
+// or for a link to a folder of audio files without an index page,
+// `[The album](album/)`. Tracks are titled from their tags (or file names),
+// and the playlist is also written as `.m3u` and `.pls` files for other players.
+
+// one track, with its url relative to the page (and to the playlist files,
+// which are written next to the page)
+#[derive(Debug, Default)]
+pub struct Track {
+    pub url: String,
+    pub mimetype: String,
+    pub title: String,
+    pub artist: Option<String>,
+    pub duration: Option<u64>,
+    pub duration_text: Option<String>,
+}
+
+fn href(url: &str) -> String {
+    let mut escaped = String::new();
+    escape_href(&mut escaped, url).expect("write to String");
+    escaped
+}
+
+// switches the player to a clicked track, and on to the next one at the end
+const PLAYLIST_SCRIPT: &str = "<script>\n\
+document.querySelectorAll('.playlist:not([data-ready])').forEach(function (playlist) {\n\
+  playlist.setAttribute('data-ready', '');\n\
+  var player = playlist.querySelector('audio');\n\
+  var links = Array.prototype.slice.call(playlist.querySelectorAll('.playlist-tracks a'));\n\
+  function play(i) {\n\
+    links.forEach(function (link, j) { link.classList.toggle('playing', i === j); });\n\
+    player.src = links[i].href;\n\
+    player.play();\n\
+  }\n\
+  links.forEach(function (link, i) {\n\
+    link.addEventListener('click', function (e) { e.preventDefault(); play(i); });\n\
+  });\n\
+  player.addEventListener('ended', function () {\n\
+    var i = links.findIndex(function (link) { return link.href === player.src; });\n\
+    if (i >= 0 && i + 1 < links.length) { play(i + 1); }\n\
+  });\n\
+});\n\
+</script>\n";
+
+// the player and track list, with links to the playlist files when given
+// as (m3u url, pls url), and the script switching tracks unless `epub`
+pub fn html(title: &str, tracks: &[Track], files: Option<(&str, &str)>, epub: bool) -> String {
+    let mut html = String::from("<div class=\"playlist\">\n");
+    if !title.is_empty() {
+        html.push_str(&format!("<p class=\"playlist-title\">{}</p>\n", escape_html(title)));
+    }
+    if let Some(first) = tracks.first() {
+        html.push_str(&format!(
+            "<audio controls preload=\"none\" src=\"{}\">Your browser does not support the audio element.</audio>\n",
+            href(&first.url)
+        ));
+    }
+    html.push_str("<ol class=\"playlist-tracks\">\n");
+    for track in tracks {
+        html.push_str(&format!(
+            "<li><a href=\"{}\" type=\"{}\">{}</a>",
+            href(&track.url),
+            track.mimetype,
+            escape_html(&track.title)
+        ));
+        if let Some(artist) = &track.artist {
+            html.push_str(&format!(" <span class=\"audio-artist\">{}</span>", escape_html(artist)));
+        }
+        if let Some(duration) = &track.duration_text {
+            html.push_str(&format!(" <span class=\"audio-duration\">{}</span>", duration));
+        }
+        html.push_str("</li>\n");
+    }
+    html.push_str("</ol>\n");
+    if let Some((m3u, pls)) = files {
+        html.push_str(&format!(
+            "<p class=\"playlist-files\"><a href=\"{}\">M3U</a> <a href=\"{}\">PLS</a></p>\n",
+            href(m3u),
+            href(pls)
+        ));
+    }
+    html.push_str("</div>\n");
+    if !epub {
+        html.push_str(PLAYLIST_SCRIPT);
+    }
+    html
+}
+
+// "Artist - Title" as shown by players
+fn display_title(track: &Track) -> String {
+    match &track.artist {
+        Some(artist) => format!("{} - {}", artist, track.title),
+        None => track.title.clone(),
+    }
+}
+
+pub fn m3u(tracks: &[Track]) -> String {
+    let mut m3u = String::from("#EXTM3U\n");
+    for track in tracks {
+        let duration = track.duration.map(|seconds| seconds as i64).unwrap_or(-1);
+        m3u.push_str(&format!("#EXTINF:{},{}\n{}\n", duration, display_title(track), track.url));
+    }
+    m3u
+}
+
+pub fn pls(tracks: &[Track]) -> String {
+    let mut pls = String::from("[playlist]\n");
+    for (i, track) in tracks.iter().enumerate() {
+        let duration = track.duration.map(|seconds| seconds as i64).unwrap_or(-1);
+        pls.push_str(&format!(
+            "File{n}={}\nTitle{n}={}\nLength{n}={}\n",
+            track.url,
+            display_title(track),
+            duration,
+            n = i + 1
+        ));
+    }
+    pls.push_str(&format!("NumberOfEntries={}\nVersion=2\n", tracks.len()));
+    pls
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_playlist_files() {
+        let tracks = vec![
+            Track {
+                url: "album/01-intro.mp3".into(),
+                title: "Intro".into(),
+                artist: Some("The Band".into()),
+                duration: Some(62),
+                ..Default::default()
+            },
+            Track {
+                url: "album/02-theme.mp3".into(),
+                title: "02-theme".into(),
+                ..Default::default()
+            },
+        ];
+        assert_eq!(
+            m3u(&tracks),
+            "#EXTM3U\n#EXTINF:62,The Band - Intro\nalbum/01-intro.mp3\n#EXTINF:-1,02-theme\nalbum/02-theme.mp3\n"
+        );
+        assert_eq!(
+            pls(&tracks),
+            "[playlist]\nFile1=album/01-intro.mp3\nTitle1=The Band - Intro\nLength1=62\n\
+             File2=album/02-theme.mp3\nTitle2=02-theme\nLength2=-1\nNumberOfEntries=2\nVersion=2\n"
+        );
+    }
+}
@@ -0,0 +1,280 @@
+use crate::math::{find_inline_end, latex_to_mathml};
+use crate::shortcode::Shortcode;
+use pulldown_cmark::{Event, Options, Parser, Tag};
+use std::ops::Range;
+
+// Math (`$x^2$`, `$$\sum_i x_i$$`) and shortcodes (`{{< youtube id >}}`,
+// `:::tip` containers) are expanded before markdown is parsed: each one is
+// rendered to html and replaced by a placeholder, so markdown syntax like
+// `_` or `\\` inside them is left alone. After parsing, placeholders in the
+// event stream are swapped for the rendered html.
+
+const PLACEHOLDER_START: char = '\u{E000}';
+const PLACEHOLDER_END: char = '\u{E001}';
+
+pub struct Preprocessed {
+    // markdown with rendered spans replaced by placeholders
+    pub text: String,
+    rendered: Vec<Rendered>,
+    // (offset in `text`, offset in the original markdown) after each placeholder
+    offsets: Vec<(usize, usize)>,
+    // (offset in the original markdown, message) for spans that failed to render
+    pub warnings: Vec<(usize, String)>,
+}
+
+struct Rendered {
+    html: String,
+    // markdown source of the span
+    source: String,
+    // plain text of the span, for the search index
+    text: String,
+    // replaces the whole paragraph when it stands alone in one
+    block: bool,
+}
+
+// byte ranges of code, where neither math nor shortcodes are expanded,
+// and of raw html, where `$` is not math
+fn literal_ranges(markdown: &str, options: Options) -> (Vec<Range<usize>>, Vec<Range<usize>>) {
+    let mut code = Vec::new();
+    let mut html = Vec::new();
+    for (event, range) in Parser::new_ext(markdown, options).into_offset_iter() {
+        match event {
+            Event::Start(Tag::CodeBlock(_)) | Event::Code(_) => code.push(range),
+            Event::Html(_) => html.push(range),
+            _ => {}
+        }
+    }
+    (code, html)
+}
+
+impl Preprocessed {
+    // expand math (if enabled) and shortcodes in the markdown;
+    // `render_shortcode` returns the html for each shortcode found,
+    // along with its plain text for the search index
+    pub fn new(
+        markdown: &str,
+        options: Options,
+        math: bool,
+        render_shortcode: &mut dyn FnMut(&Shortcode) -> anyhow::Result<(String, String)>,
+    ) -> Self {
+        let (code, html) = literal_ranges(markdown, options);
+        let mut pre = Preprocessed {
+            text: String::with_capacity(markdown.len()),
+            rendered: Vec::new(),
+            offsets: Vec::new(),
+            warnings: Vec::new(),
+        };
+        let mut copied = 0; // markdown[..copied] has been handled
+        let mut pos = 0;
+        while let Some(found) = markdown[pos..].find(['$', '{', ':']) {
+            let start = pos + found;
+            pos = start + 1;
+            if let Some(range) = code.iter().find(|r| r.contains(&start)) {
+                pos = range.end;
+                continue;
+            }
+            if markdown[..start].ends_with('\\') {
+                continue;
+            }
+            let shortcode = match markdown.as_bytes()[start] {
+                b'{' => Shortcode::parse(markdown, start),
+                b':' => Shortcode::parse_container(markdown, start),
+                _ => None,
+            };
+            if let Some(shortcode) = shortcode {
+                match render_shortcode(&shortcode) {
+                    Ok((html, text)) => {
+                        pre.replace(markdown, copied..start, shortcode.end, html, text, true);
+                        pre.offsets.push((pre.text.len(), shortcode.end));
+                        copied = shortcode.end;
+                    }
+                    Err(e) => pre.warnings.push((
+                        start,
+                        format!("could not render shortcode '{}', left as is: {:#}", shortcode.name, e),
+                    )),
+                }
+                pos = shortcode.end;
+                continue;
+            }
+            if !math || !markdown[start..].starts_with('$') {
+                continue;
+            }
+            if let Some(range) = html.iter().find(|r| r.contains(&start)) {
+                pos = range.end;
+                continue;
+            }
+            let display = markdown[start..].starts_with("$$");
+            let (tex_range, end) = if display {
+                match markdown[start + 2..].find("$$") {
+                    Some(len) => (start + 2..start + 2 + len, start + 2 + len + 2),
+                    None => continue,
+                }
+            } else {
+                match find_inline_end(markdown, start + 1) {
+                    Some(close) => (start + 1..close, close + 1),
+                    None => continue,
+                }
+            };
+            if code.iter().chain(&html).any(|r| r.start < end && tex_range.start < r.end) {
+                // code spans take precedence over math
+                continue;
+            }
+            match latex_to_mathml(&markdown[tex_range.clone()], display) {
+                Ok(mathml) => {
+                    let tex = markdown[tex_range].to_string();
+                    pre.replace(markdown, copied..start, end, mathml, tex, display);
+                    pre.offsets.push((pre.text.len(), end));
+                    copied = end;
+                }
+                Err(e) => pre.warnings.push((
+                    start,
+                    format!("could not render math, left as is: {} in `{}`", e, &markdown[tex_range]),
+                )),
+            }
+            pos = end;
+        }
+        pre.text.push_str(&markdown[copied..]);
+        pre
+    }
+
+    // copy `markdown[before]` followed by a placeholder for `html`,
+    // which replaces the source up to `end`
+    fn replace(&mut self, markdown: &str, before: Range<usize>, end: usize, html: String, text: String, block: bool) {
+        let source = markdown[before.end..end].to_string();
+        self.text.push_str(&markdown[before]);
+        self.text.push(PLACEHOLDER_START);
+        self.text.push_str(&self.rendered.len().to_string());
+        self.text.push(PLACEHOLDER_END);
+        self.rendered.push(Rendered { html, source, text, block });
+    }
+
+    // text from `text` with placeholders swapped back for their markdown source
+    pub fn source_text(&self, text: &str) -> String {
+        self.swap_placeholders(text, |rendered| &rendered.source)
+    }
+
+    // text from `text` with placeholders swapped for their plain text
+    pub fn search_text(&self, text: &str) -> String {
+        self.swap_placeholders(text, |rendered| &rendered.text)
+    }
+
+    fn swap_placeholders(&self, text: &str, swap: impl Fn(&Rendered) -> &str) -> String {
+        let mut events = Vec::new();
+        let found = self.split_placeholders(text, &mut events);
+        let mut found = found.iter();
+        events
+            .iter()
+            .map(|event| match event {
+                Event::Html(_) => found.next().map(|rendered| swap(rendered)).unwrap_or_default(),
+                Event::Text(text) => text.as_ref(),
+                _ => "",
+            })
+            .collect()
+    }
+
+    // map an offset in `text` back to the original markdown, for line numbers
+    pub fn original_offset(&self, offset: usize) -> usize {
+        match self.offsets.iter().rev().find(|(text_offset, _)| *text_offset <= offset) {
+            Some((text_offset, original)) => original + (offset - text_offset),
+            None => offset,
+        }
+    }
+
+    // split text around placeholders into text and html events, returning
+    // the rendered spans that were found
+    fn split_placeholders<'a>(&self, text: &str, events: &mut Vec<Event<'a>>) -> Vec<&Rendered> {
+        let mut found = Vec::new();
+        let mut rest = text;
+        while let Some(start) = rest.find(PLACEHOLDER_START) {
+            let end = match rest[start..].find(PLACEHOLDER_END) {
+                Some(len) => start + len,
+                None => break,
+            };
+            let rendered = rest[start + PLACEHOLDER_START.len_utf8()..end]
+                .parse::<usize>()
+                .ok()
+                .and_then(|i| self.rendered.get(i));
+            if let Some(rendered) = rendered {
+                if start > 0 {
+                    events.push(Event::Text(rest[..start].to_string().into()));
+                }
+                events.push(Event::Html(rendered.html.clone().into()));
+                found.push(rendered);
+            }
+            rest = &rest[end + PLACEHOLDER_END.len_utf8()..];
+        }
+        if !rest.is_empty() {
+            events.push(Event::Text(rest.to_string().into()));
+        }
+        found
+    }
+
+    // replace placeholders in text events with their html; a paragraph holding
+    // just one block (display math or a shortcode) is replaced by the block itself
+    pub fn replace_placeholders<'a>(&self, events: Vec<Event<'a>>) -> Vec<Event<'a>> {
+        if self.rendered.is_empty() {
+            return events;
+        }
+        let mut result = Vec::with_capacity(events.len());
+        let mut i = 0;
+        while i < events.len() {
+            if let (Event::Start(Tag::Paragraph), Some(Event::Text(text)), Some(Event::End(Tag::Paragraph))) =
+                (&events[i], events.get(i + 1), events.get(i + 2))
+            {
+                let mut single = Vec::new();
+                let found = self.split_placeholders(text.trim(), &mut single);
+                if let ([Event::Html(html)], [rendered]) = (single.as_slice(), found.as_slice()) {
+                    if rendered.block {
+                        result.push(Event::Html(format!("{}\n", html).into()));
+                        i += 3;
+                        continue;
+                    }
+                }
+            }
+            match &events[i] {
+                Event::Text(text) if text.contains(PLACEHOLDER_START) => {
+                    self.split_placeholders(text, &mut result);
+                }
+                event => result.push(event.clone()),
+            }
+            i += 1;
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prices_are_not_math() {
+        let markdown = "Price $5 and $10. Inline $x$, US$3 and a$b$.\n\n$a\n\nb$\n";
+        let pre = Preprocessed::new(markdown, Options::empty(), true, &mut |_| Ok(Default::default()));
+        assert_eq!(pre.rendered.len(), 1);
+        assert_eq!(pre.rendered[0].source, "$x$");
+        assert!(pre.text.starts_with("Price $5 and $10. Inline \u{E000}0\u{E001}, US$3 and a$b$."));
+    }
+
+    #[test]
+    fn test_placeholders_skip_code() {
+        let markdown = "Price $5 and `$x$` but $a_b$ and\n\n$$\\alpha$$\n\n`{{< note >}}`\n\n{{< note kind=tip >}}\n";
+        let mut names = Vec::new();
+        let pre = Preprocessed::new(markdown, Options::empty(), true, &mut |shortcode| {
+            names.push(shortcode.name.clone());
+            Ok(("<aside></aside>".into(), "note".into()))
+        });
+        assert_eq!(names, vec!["note"]);
+        assert_eq!(pre.rendered.len(), 3);
+        assert!(pre.text.starts_with("Price $5 and `$x$` but \u{E000}0\u{E001} and"));
+        assert!(pre.warnings.is_empty());
+        assert_eq!(pre.search_text(&pre.text[..pre.text.find('\n').expect("line")]), "Price $5 and `$x$` but a_b and");
+
+        let events: Vec<Event> = Parser::new(&pre.text).collect();
+        let events = pre.replace_placeholders(events);
+        let html_count = events.iter().filter(|e| matches!(e, Event::Html(_))).count();
+        assert_eq!(html_count, 3);
+        // display math and the shortcode are not wrapped in paragraphs
+        assert_eq!(events.iter().filter(|e| matches!(e, Event::Start(Tag::Paragraph))).count(), 2);
+    }
+}
@@ -0,0 +1,73 @@
+use crate::util::{escape_html, relative_url};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// a small html page at an old url that sends visitors to the page's new url
+pub struct Redirect {
+    // markdown file that declared the alias
+    pub source_path: PathBuf,
+    // redirect file, relative to the output directory
+    pub rel_outpath: PathBuf,
+    // site-relative url of the redirect page itself
+    pub url: String,
+    // site-relative url of the page to redirect to
+    pub target_url: String,
+}
+
+impl Redirect {
+    pub fn html(&self) -> String {
+        let target = escape_html(&relative_url(&self.url, &self.target_url));
+        format!(
+            "<!DOCTYPE html>
+<html lang=\"en\">
+<head>
+    <meta charset=\"UTF-8\">
+    <title>Redirecting&hellip;</title>
+    <link rel=\"canonical\" href=\"{target}\" />
+    <meta http-equiv=\"refresh\" content=\"0; url={target}\" />
+    <meta name=\"robots\" content=\"noindex\" />
+</head>
+<body>
+    <p>This page has moved to <a href=\"{target}\">{target}</a>.</p>
+</body>
+</html>
+",
+            target = target
+        )
+    }
+
+    pub fn write<P: AsRef<Path>>(&self, out_dir: P) -> anyhow::Result<()> {
+        let outpath = out_dir.as_ref().join(&self.rel_outpath);
+        info!(
+            "redirect-> {}\t{}",
+            outpath.display(),
+            self.target_url
+        );
+        if let Some(dir) = outpath.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&outpath, self.html())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redirect_html() {
+        let redirect = Redirect {
+            source_path: PathBuf::from("markdown/new.md"),
+            rel_outpath: PathBuf::from("old/post.html"),
+            url: "old/post.html".into(),
+            target_url: "2022/new&improved/".into(),
+        };
+        let html = redirect.html();
+        // relative to the old url, so the site works from any base path
+        let target = "../2022/new&amp;improved/";
+        assert!(html.contains(&format!("<link rel=\"canonical\" href=\"{}\" />", target)), "{}", html);
+        assert!(html.contains(&format!("<meta http-equiv=\"refresh\" content=\"0; url={}\" />", target)), "{}", html);
+        assert!(html.contains(&format!("<a href=\"{}\">", target)), "{}", html);
+    }
+}
@@ -0,0 +1,420 @@
+// With `--sanitize`, raw html in markdown is filtered against an allowlist
+// of tags and attributes, for sites publishing contributed markdown. Tags
+// that aren't allowed are removed but their text is kept, except for
+// elements like `<script>` whose content is removed too. Comments,
+// `on...` handlers, `style` and urls with a scheme other than http(s) or
+// mailto (like `javascript:`) are removed as well, including from markdown
+// links and images, and from shortcode arguments holding urls. Html
+// generated by webgenr itself (players, highlighted code) and by shortcode
+// templates is not filtered, but the markdown inside a shortcode is.
+
+const ALLOWED_TAGS: [&str; 56] = [
+    "a", "abbr", "audio", "b", "bdi", "bdo", "blockquote", "br", "caption", "cite", "code", "col", "colgroup", "dd",
+    "del", "details", "dfn", "div", "dl", "dt", "em", "figcaption", "figure", "h1", "h2", "h3", "h4", "h5", "h6",
+    "hr", "i", "img", "ins", "kbd", "li", "mark", "ol", "p", "pre", "q", "s", "samp", "small", "source", "span",
+    "strong", "sub", "summary", "sup", "table", "tbody", "td", "th", "thead", "tr", "ul",
+];
+// removed along with their content
+const DROPPED_ELEMENTS: [&str; 9] =
+    ["script", "style", "iframe", "object", "embed", "template", "noscript", "textarea", "title"];
+// allowed on any allowed tag, along with `aria-*`
+const GLOBAL_ATTRIBUTES: [&str; 5] = ["class", "id", "title", "lang", "dir"];
+const TAG_ATTRIBUTES: [(&str, &[&str]); 12] = [
+    ("a", &["href", "name", "hreflang"]),
+    ("img", &["src", "alt", "width", "height", "loading"]),
+    ("audio", &["src", "controls", "preload"]),
+    ("source", &["src", "type"]),
+    ("td", &["colspan", "rowspan", "align"]),
+    ("th", &["colspan", "rowspan", "align", "scope"]),
+    ("col", &["span"]),
+    ("ol", &["start", "reversed", "type"]),
+    ("details", &["open"]),
+    ("blockquote", &["cite"]),
+    ("del", &["cite", "datetime"]),
+    ("ins", &["cite", "datetime"]),
+];
+const URL_ATTRIBUTES: [&str; 3] = ["href", "src", "cite"];
+// named shortcode arguments holding a url
+pub const URL_ARGUMENTS: [&str; 5] = ["src", "href", "link", "poster", "url"];
+// relative urls are allowed too
+const SAFE_SCHEMES: [&str; 3] = ["http", "https", "mailto"];
+
+// The named character references for ASCII characters, which are the only
+// ones that can change how a url is parsed, with whether browsers also
+// accept them without `;` (all other named references are for non-ASCII
+// characters, so they are left as written)
+const NAMED_REFERENCES: [(&str, &str, bool); 46] = [
+    ("Tab", "\t", false), ("NewLine", "\n", false), ("excl", "!", false), ("quot", "\"", true),
+    ("QUOT", "\"", true), ("num", "#", false), ("dollar", "$", false), ("percnt", "%", false),
+    ("amp", "&", true), ("AMP", "&", true), ("apos", "'", false), ("lpar", "(", false), ("rpar", ")", false),
+    ("ast", "*", false), ("midast", "*", false), ("plus", "+", false), ("comma", ",", false),
+    ("period", ".", false), ("sol", "/", false), ("colon", ":", false), ("semi", ";", false),
+    ("lt", "<", true), ("LT", "<", true), ("equals", "=", false), ("gt", ">", true), ("GT", ">", true),
+    ("quest", "?", false), ("commat", "@", false), ("lsqb", "[", false), ("lbrack", "[", false),
+    ("bsol", "\\", false), ("rsqb", "]", false), ("rbrack", "]", false), ("Hat", "^", false),
+    ("lowbar", "_", false), ("UnderBar", "_", false), ("grave", "`", false),
+    ("DiacriticalGrave", "`", false), ("lcub", "{", false), ("lbrace", "{", false),
+    ("verbar", "|", false), ("vert", "|", false), ("VerticalLine", "|", false), ("rcub", "}", false),
+    ("rbrace", "}", false), ("fjlig", "fj", false),
+];
+// what numeric references to 0x80..=0x9f mean (windows-1252), or None
+// where the control character itself is kept
+const C1_REPLACEMENTS: [Option<char>; 32] = [
+    Some('\u{20ac}'), None, Some('\u{201a}'), Some('\u{192}'), Some('\u{201e}'), Some('\u{2026}'),
+    Some('\u{2020}'), Some('\u{2021}'), Some('\u{2c6}'), Some('\u{2030}'), Some('\u{160}'),
+    Some('\u{2039}'), Some('\u{152}'), None, Some('\u{17d}'), None, None, Some('\u{2018}'),
+    Some('\u{2019}'), Some('\u{201c}'), Some('\u{201d}'), Some('\u{2022}'), Some('\u{2013}'),
+    Some('\u{2014}'), Some('\u{2dc}'), Some('\u{2122}'), Some('\u{161}'), Some('\u{203a}'),
+    Some('\u{153}'), None, Some('\u{17e}'), Some('\u{178}'),
+];
+
+// whether a url is relative or uses a safe scheme, read the way a browser
+// reads an attribute: character references decoded, then tabs, newlines
+// and other control characters ignored
+pub fn is_safe_url(url: &str) -> bool {
+    let url: String = decode_entities(url)
+        .chars()
+        .filter(|c| !c.is_ascii_whitespace() && !c.is_control())
+        .collect();
+    match scheme(&url) {
+        Some(scheme) => SAFE_SCHEMES.contains(&scheme.to_ascii_lowercase().as_str()),
+        None => true,
+    }
+}
+
+// the scheme of a url as in the URL standard: a letter followed by letters,
+// digits, `+`, `-` or `.`, up to a `:`; without one the url is relative
+fn scheme(url: &str) -> Option<&str> {
+    let scheme = &url[..url.find(':')?];
+    let mut chars = scheme.chars();
+    let valid = matches!(chars.next(), Some(c) if c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'));
+    valid.then_some(scheme)
+}
+
+// character references in an attribute value decoded as browsers do,
+// with or without the closing `;`
+fn decode_entities(text: &str) -> String {
+    let mut decoded = String::new();
+    let mut rest = text;
+    while let Some(amp) = rest.find('&') {
+        decoded.push_str(&rest[..amp]);
+        rest = &rest[amp + 1..];
+        match decode_reference(rest) {
+            Some((replacement, len)) => {
+                decoded.push_str(&replacement);
+                rest = &rest[len..];
+            }
+            None => decoded.push('&'),
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+// the reference at the start of `text` (just after `&`), decoded,
+// with its length
+fn decode_reference(text: &str) -> Option<(String, usize)> {
+    if let Some(number) = text.strip_prefix('#') {
+        let (radix, start) = match number.starts_with(['x', 'X']) {
+            true => (16, 2),
+            false => (10, 1),
+        };
+        let digits = &text[start..];
+        let len = digits.find(|c: char| !c.is_digit(radix)).unwrap_or(digits.len());
+        if len == 0 {
+            return None;
+        }
+        let code = digits[..len].chars().fold(0u32, |code, digit| {
+            code.saturating_mul(radix).saturating_add(digit.to_digit(radix).unwrap_or_default())
+        });
+        let c = match code {
+            0x80..=0x9f => C1_REPLACEMENTS[(code - 0x80) as usize].unwrap_or(char::from_u32(code).expect("c1 control")),
+            code => char::from_u32(code).filter(|c| *c != '\0').unwrap_or('\u{fffd}'),
+        };
+        let end = start + len + usize::from(digits[len..].starts_with(';'));
+        return Some((c.to_string(), end));
+    }
+    let len = text.find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(text.len());
+    let (_, replacement, legacy) = NAMED_REFERENCES.iter().find(|(name, ..)| *name == &text[..len])?;
+    if text[len..].starts_with(';') {
+        return Some((replacement.to_string(), len + 1));
+    }
+    // in attributes, references without `;` aren't decoded before `=`
+    (*legacy && !text[len..].starts_with('=')).then(|| (replacement.to_string(), len))
+}
+
+// an attribute value decoded and escaped again, so browsers read the text
+// that was checked. Named references that aren't decoded here (for
+// non-ASCII characters, like `&eacute;`) are kept
+fn escape_attribute(value: &str) -> String {
+    let decoded = decode_entities(value);
+    let mut escaped = String::with_capacity(decoded.len());
+    for (i, c) in decoded.char_indices() {
+        match c {
+            '&' if is_kept_reference(&decoded[i + 1..]) => escaped.push('&'),
+            '&' => escaped.push_str("&amp;"),
+            '"' => escaped.push_str("&quot;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// whether `text` (just after `&`) is a named reference to a non-ASCII character
+fn is_kept_reference(text: &str) -> bool {
+    let len = text.find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(text.len());
+    let name = &text[..len];
+    name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && text[len..].starts_with(';')
+        && !NAMED_REFERENCES.iter().any(|(known, ..)| *known == name)
+}
+
+fn is_allowed_attribute(tag: &str, name: &str) -> bool {
+    GLOBAL_ATTRIBUTES.contains(&name)
+        || name.starts_with("aria-")
+        || TAG_ATTRIBUTES.iter().any(|(t, names)| *t == tag && names.contains(&name))
+}
+
+// filters the raw html of one document, which pulldown-cmark can split
+// over several events (so a tag or an element being dropped can continue)
+#[derive(Debug, Default)]
+pub struct Sanitizer {
+    // an element whose content is being dropped, like "script"
+    dropping: Option<String>,
+    // the start of a tag that continues in the next event
+    partial_tag: String,
+}
+
+impl Sanitizer {
+    // the allowed html in `html`, with a description of each removal
+    pub fn clean(&mut self, html: &str, removed: &mut Vec<String>) -> String {
+        let html = std::mem::take(&mut self.partial_tag) + html;
+        let mut clean = String::new();
+        let mut rest = html.as_str();
+        while !rest.is_empty() {
+            if let Some(element) = &self.dropping {
+                let lower = rest.to_ascii_lowercase();
+                match lower.find(&format!("</{}", element)) {
+                    Some(start) => {
+                        let end = rest[start..].find('>').map(|end| start + end + 1).unwrap_or(rest.len());
+                        rest = &rest[end..];
+                        self.dropping = None;
+                    }
+                    None => rest = "",
+                }
+                continue;
+            }
+            let lt = match rest.find('<') {
+                Some(lt) => lt,
+                None => {
+                    clean.push_str(rest);
+                    break;
+                }
+            };
+            clean.push_str(&rest[..lt]);
+            rest = &rest[lt..];
+            if rest.starts_with("<!--") {
+                rest = rest.find("-->").map(|end| &rest[end + 3..]).unwrap_or("");
+                removed.push("comment".into());
+                continue;
+            }
+            let after = rest[1..].chars().next();
+            if !matches!(after, Some(c) if c.is_ascii_alphabetic() || c == '/' || c == '!' || c == '?') {
+                clean.push_str("&lt;");
+                rest = &rest[1..];
+                continue;
+            }
+            let end = match tag_end(rest) {
+                Some(end) => end,
+                None => {
+                    self.partial_tag = rest.to_string();
+                    break;
+                }
+            };
+            let tag = &rest[1..end];
+            rest = &rest[end + 1..];
+            if let Some(closing) = tag.strip_prefix('/') {
+                let name = closing.trim().to_ascii_lowercase();
+                if ALLOWED_TAGS.contains(&name.as_str()) {
+                    clean.push_str(&format!("</{}>", name));
+                }
+                continue;
+            }
+            if !tag.starts_with(|c: char| c.is_ascii_alphabetic()) {
+                removed.push(format!("<{}>", tag));
+                continue;
+            }
+            clean.push_str(&self.clean_tag(tag, removed));
+        }
+        clean
+    }
+
+    // the end of the document, where a tag can't continue
+    pub fn finish(&mut self, removed: &mut Vec<String>) {
+        if !std::mem::take(&mut self.partial_tag).is_empty() {
+            removed.push("unterminated tag".into());
+        }
+    }
+
+    // an opening tag (without `<` and `>`), rebuilt from its allowed parts
+    fn clean_tag(&mut self, tag: &str, removed: &mut Vec<String>) -> String {
+        let name_end = tag.find(|c: char| c.is_ascii_whitespace() || c == '/').unwrap_or(tag.len());
+        let name = tag[..name_end].to_ascii_lowercase();
+        let self_closing = tag.trim_end().ends_with('/');
+        if DROPPED_ELEMENTS.contains(&name.as_str()) {
+            removed.push(format!("<{}> element", name));
+            if !self_closing {
+                self.dropping = Some(name);
+            }
+            return String::new();
+        }
+        if !ALLOWED_TAGS.contains(&name.as_str()) {
+            removed.push(format!("<{}> tag", name));
+            return String::new();
+        }
+        let mut clean = format!("<{}", name);
+        for (attribute, value) in attributes(&tag[name_end..]) {
+            if !is_allowed_attribute(&name, &attribute) {
+                removed.push(format!("{} attribute of <{}>", attribute, name));
+                continue;
+            }
+            match value {
+                Some(value) if URL_ATTRIBUTES.contains(&attribute.as_str()) && !is_safe_url(&value) => {
+                    removed.push(format!("{} \"{}\" of <{}>", attribute, value, name));
+                }
+                Some(value) => clean.push_str(&format!(" {}=\"{}\"", attribute, escape_attribute(&value))),
+                None => clean.push_str(&format!(" {}", attribute)),
+            }
+        }
+        clean.push_str(if self_closing { " />" } else { ">" });
+        clean
+    }
+}
+
+// the index of the `>` ending the tag starting `html`, outside quotes
+fn tag_end(html: &str) -> Option<usize> {
+    let mut quote = None;
+    for (i, c) in html.char_indices() {
+        match (quote, c) {
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (Some(q), c) if c == q => quote = None,
+            (None, '>') => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+// the attributes of a tag, with lowercase names and their raw values
+fn attributes(mut text: &str) -> Vec<(String, Option<String>)> {
+    let mut attributes = Vec::new();
+    loop {
+        text = text.trim_start_matches(|c: char| c.is_ascii_whitespace() || c == '/');
+        if text.is_empty() {
+            return attributes;
+        }
+        let name_end = text
+            .find(|c: char| c.is_ascii_whitespace() || c == '=' || c == '/')
+            .unwrap_or(text.len());
+        let name = text[..name_end].to_ascii_lowercase();
+        text = text[name_end..].trim_start();
+        let value = match text.strip_prefix('=') {
+            Some(value) => {
+                let value = value.trim_start();
+                let (value, rest) = match value.chars().next() {
+                    Some(quote @ ('"' | '\'')) => {
+                        let end = value[1..].find(quote).map(|end| end + 1).unwrap_or(value.len());
+                        (&value[1..end], value.get(end + 1..).unwrap_or(""))
+                    }
+                    _ => {
+                        let end = value.find(|c: char| c.is_ascii_whitespace()).unwrap_or(value.len());
+                        (&value[..end], &value[end..])
+                    }
+                };
+                text = rest;
+                Some(value.to_string())
+            }
+            None => None,
+        };
+        if !name.is_empty() {
+            attributes.push((name, value));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize() {
+        let mut sanitizer = Sanitizer::default();
+        let mut removed = Vec::new();
+        let html = "<p class=\"x\" onclick=\"steal()\">Hi <a href=\"jav&#x61;script:alert(1)\" title='a \"b\"'>there</a>\
+                    <script>alert(1)</script><font color=red>!</font><!-- note --><br/> 1 < 2</p>";
+        assert_eq!(
+            sanitizer.clean(html, &mut removed),
+            "<p class=\"x\">Hi <a title=\"a &quot;b&quot;\">there</a>!<br /> 1 &lt; 2</p>"
+        );
+        assert_eq!(
+            removed,
+            vec![
+                "onclick attribute of <p>",
+                "href \"jav&#x61;script:alert(1)\" of <a>",
+                "<script> element",
+                "<font> tag",
+                "comment",
+            ]
+        );
+
+        // an element split over events keeps being dropped
+        let mut removed = Vec::new();
+        assert_eq!(sanitizer.clean("<style>\n", &mut removed), "");
+        assert_eq!(sanitizer.clean("p { color: red }\n", &mut removed), "");
+        assert_eq!(sanitizer.clean("</style>\n<em>ok</em>\n", &mut removed), "\n<em>ok</em>\n");
+        assert_eq!(sanitizer.clean("<div\n", &mut removed), "");
+        assert_eq!(sanitizer.clean("  onmouseover=x class=\"y\">\n", &mut removed), "<div class=\"y\">\n");
+        assert_eq!(removed, vec!["<style> element", "onmouseover attribute of <div>"]);
+
+        assert!(is_safe_url("https://example.com/a:b"));
+        assert!(is_safe_url("docs/page.html#x:y"));
+        assert!(!is_safe_url(" JavaScript:alert(1)"));
+        assert!(!is_safe_url("java\tscript:alert(1)"));
+        assert!(!is_safe_url("data:text/html,<script>"));
+    }
+
+    #[test]
+    fn test_hidden_schemes() {
+        for url in [
+            "javascript&#58alert(1)",
+            "javascript&#x3A(alert(1))",
+            "javascript&#X3a;alert(1)",
+            "jav&#97script:alert(1)",
+            "&#0000106avascript:alert(1)",
+            "javascript&colon;alert(1)",
+            "java\nscript:alert(1)",
+            "java&#9;script:alert(1)",
+            "java&Tab;script:alert(1)",
+            "java&NewLine;script:alert(1)",
+            "\u{1}javascript:alert(1)",
+            "JaVaScRiPt:alert(1)",
+            "tel:123",
+        ] {
+            assert!(!is_safe_url(url), "{}", url);
+        }
+        assert!(is_safe_url("MAILTO:someone@example.com"));
+        assert!(is_safe_url("//example.com/a"));
+        assert!(is_safe_url("a+b/c:d"));
+
+        let mut removed = Vec::new();
+        let html = "<a href=\"javascript&#58alert(1)\">x</a><a href=\"?x=1&y=2&amp;z=&eacute;&amp;colon;\">y</a>";
+        assert_eq!(
+            Sanitizer::default().clean(html, &mut removed),
+            "<a>x</a><a href=\"?x=1&amp;y=2&amp;z=&eacute;&amp;colon;\">y</a>"
+        );
+        assert_eq!(removed, vec!["href \"javascript&#58alert(1)\" of <a>"]);
+    }
+}
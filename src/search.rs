@@ -0,0 +1,162 @@
+use pulldown_cmark::{Event, Tag};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::BufWriter;
+use std::path::Path;
+
+pub const SEARCH_INDEX_FILE: &str = "search_index.json";
+pub const SEARCH_INVERTED_FILE: &str = "search_inverted.json";
+
+// one entry per generated page, written as a JSON array to `search_index.json`
+#[derive(Debug, Default, Serialize)]
+pub struct SearchEntry {
+    pub title: String,
+    pub url: String,
+    pub headings: Vec<String>,
+    pub body: String,
+}
+
+// text content of a markdown document, with all markup stripped
+#[derive(Debug, Default)]
+pub struct PlainText {
+    pub headings: Vec<String>,
+    pub body: String,
+}
+
+impl PlainText {
+    // walk a pulldown-cmark event stream, taken before code and shortcodes
+    // become html, and keep only the text; raw html events are dropped
+    pub fn from_events(events: &[Event]) -> Self {
+        let mut plain = PlainText::default();
+        let mut heading: Option<String> = None;
+        for event in events {
+            match event {
+                Event::Start(Tag::Heading(..)) => heading = Some(String::new()),
+                Event::End(Tag::Heading(..)) => {
+                    if let Some(text) = heading.take() {
+                        plain.headings.push(collapse_whitespace(&text));
+                    }
+                    plain.body.push(' ');
+                }
+                Event::Text(text) | Event::Code(text) => {
+                    plain.body.push_str(text);
+                    if let Some(heading_text) = heading.as_mut() {
+                        heading_text.push_str(text);
+                    }
+                }
+                Event::SoftBreak
+                | Event::HardBreak
+                | Event::Rule
+                | Event::End(Tag::Paragraph)
+                | Event::End(Tag::Item)
+                | Event::End(Tag::CodeBlock(_))
+                | Event::End(Tag::TableCell) => plain.body.push(' '),
+                _ => {}
+            }
+        }
+        plain.body = collapse_whitespace(&plain.body);
+        plain
+    }
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+// lowercase words of at least two characters
+fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| word.chars().count() > 1)
+        .map(|word| word.to_lowercase())
+}
+
+#[derive(Serialize)]
+struct InvertedDoc<'a> {
+    title: &'a str,
+    url: &'a str,
+}
+
+// `index` maps each token to a list of [doc number, occurrences]
+#[derive(Serialize)]
+struct InvertedIndex<'a> {
+    docs: Vec<InvertedDoc<'a>>,
+    index: BTreeMap<String, Vec<(usize, usize)>>,
+}
+
+impl<'a> InvertedIndex<'a> {
+    fn new(entries: &'a [SearchEntry]) -> Self {
+        let mut index: BTreeMap<String, Vec<(usize, usize)>> = BTreeMap::new();
+        for (doc_num, entry) in entries.iter().enumerate() {
+            let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+            let fields = std::iter::once(entry.title.as_str())
+                .chain(entry.headings.iter().map(|h| h.as_str()))
+                .chain(std::iter::once(entry.body.as_str()));
+            for field in fields {
+                for token in tokenize(field) {
+                    *counts.entry(token).or_insert(0) += 1;
+                }
+            }
+            for (token, count) in counts {
+                index.entry(token).or_default().push((doc_num, count));
+            }
+        }
+        InvertedIndex {
+            docs: entries
+                .iter()
+                .map(|entry| InvertedDoc {
+                    title: &entry.title,
+                    url: &entry.url,
+                })
+                .collect(),
+            index,
+        }
+    }
+}
+
+// write search index files into the output directory
+pub fn write_index<P: AsRef<Path>>(
+    out_dir: P,
+    entries: &[SearchEntry],
+    inverted: bool,
+) -> anyhow::Result<()> {
+    let index_path = out_dir.as_ref().join(SEARCH_INDEX_FILE);
+    info!("search index-> {}", index_path.display());
+    let writer = BufWriter::new(fs::File::create(&index_path)?);
+    serde_json::to_writer(writer, entries)?;
+
+    if inverted {
+        let inverted_path = out_dir.as_ref().join(SEARCH_INVERTED_FILE);
+        info!("search index-> {}", inverted_path.display());
+        let writer = BufWriter::new(fs::File::create(&inverted_path)?);
+        serde_json::to_writer(writer, &InvertedIndex::new(entries))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pulldown_cmark::Parser;
+
+    #[test]
+    fn test_plain_text_strips_markup() {
+        let events: Vec<Event> = Parser::new("# Hello *world*\n\nsome `code` and [a link](x.md)\n").collect();
+        let plain = PlainText::from_events(&events);
+        assert_eq!(plain.headings, vec!["Hello world"]);
+        assert_eq!(plain.body, "Hello world some code and a link");
+    }
+
+    #[test]
+    fn test_inverted_index_counts_tokens() {
+        let entries = vec![SearchEntry {
+            title: "Apple".into(),
+            url: "index.html".into(),
+            headings: vec![],
+            body: "apple pie, a kitten".into(),
+        }];
+        let inverted = InvertedIndex::new(&entries);
+        assert_eq!(inverted.index["apple"], vec![(0, 2)]);
+        assert!(!inverted.index.contains_key("a"));
+    }
+}
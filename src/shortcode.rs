@@ -0,0 +1,220 @@
+use crate::admonition::ADMONITION_SHORTCODE;
+use std::collections::BTreeMap;
+
+// Shortcodes are template-backed components written in markdown as
+//   {{< youtube dQw4w9WgXcQ >}}
+//   {% figure src="cat.jpg" caption="A cat" %}
+// and optionally wrap a markdown body:
+//   {{< note kind=tip >}} some *markdown* {{< /note >}}
+//   {% note %} some *markdown* {% endnote %}
+// Each is rendered with the `shortcodes/<name>.hbs` template.
+// `:::kind Title` ... `:::` containers are the `admonition` shortcode.
+
+pub const SHORTCODE_TEMPLATE_DIR: &str = "shortcodes";
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Shortcode {
+    pub name: String,
+    // positional arguments, in order
+    pub args: Vec<String>,
+    // `key=value` arguments
+    pub named: BTreeMap<String, String>,
+    // markdown between the opening and closing tags, with its offset
+    pub inner: Option<(usize, String)>,
+    // offset of the opening tag
+    pub start: usize,
+    // offset just past the shortcode (after the closing tag, if any)
+    pub end: usize,
+}
+
+// the two supported tag styles: (open, close, prefix of the closing tag name)
+const STYLES: [(&str, &str, &str); 2] = [("{{<", ">}}", "/"), ("{%", "%}", "end")];
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '-'
+}
+
+// split tag contents into words, keeping "quoted strings" together
+fn split_words(contents: &str) -> Option<Vec<String>> {
+    let mut words = Vec::new();
+    let mut chars = contents.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        let mut word = String::new();
+        let mut quoted = false;
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => quoted = !quoted,
+                '\\' if quoted => word.push(chars.next()?),
+                c if c.is_whitespace() && !quoted => break,
+                c => word.push(c),
+            }
+        }
+        if quoted {
+            return None; // unterminated string
+        }
+        words.push(word);
+    }
+    Some(words)
+}
+
+// a tag starting at `start`: (style index, contents, offset past the tag)
+fn parse_tag(markdown: &str, start: usize) -> Option<(usize, &str, usize)> {
+    let rest = &markdown[start..];
+    STYLES.iter().enumerate().find_map(|(style, (open, close, _))| {
+        let after_open = rest.strip_prefix(open)?;
+        // tags don't span paragraphs
+        let len = after_open.find(close).filter(|len| !after_open[..*len].contains("\n\n"))?;
+        let end = start + open.len() + len + close.len();
+        Some((style, after_open[..len].trim(), end))
+    })
+}
+
+impl Shortcode {
+    // parse the shortcode starting at `start` in the markdown, if there is one
+    pub fn parse(markdown: &str, start: usize) -> Option<Shortcode> {
+        let (style, contents, tag_end) = parse_tag(markdown, start)?;
+        let (self_closing, contents) = match contents.strip_suffix('/') {
+            Some(contents) => (true, contents),
+            None => (false, contents),
+        };
+        let mut words = split_words(contents)?.into_iter();
+        let name = words.next()?;
+        if !name.chars().all(is_name_char) {
+            return None;
+        }
+        let mut shortcode = Shortcode {
+            name,
+            start,
+            end: tag_end,
+            ..Default::default()
+        };
+        for word in words {
+            match word.split_once('=') {
+                Some((key, value)) if !key.is_empty() && key.chars().all(is_name_char) => {
+                    shortcode.named.insert(key.to_string(), value.to_string());
+                }
+                _ => shortcode.args.push(word),
+            }
+        }
+        if !self_closing {
+            if let Some((inner_end, end)) = find_closing_tag(markdown, tag_end, style, &shortcode.name) {
+                shortcode.inner = Some((tag_end, markdown[tag_end..inner_end].to_string()));
+                shortcode.end = end;
+            }
+        }
+        Some(shortcode)
+    }
+
+    // parse a `:::kind Optional title` container starting at `start`, which
+    // must be at the beginning of a line, as an `admonition` shortcode
+    pub fn parse_container(markdown: &str, start: usize) -> Option<Shortcode> {
+        if !markdown[..start].ends_with('\n') && start != 0 {
+            return None;
+        }
+        let first_line_end = line_end(markdown, start);
+        let line = markdown[start..first_line_end].strip_prefix(":::")?.trim();
+        let (kind, title) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        if kind.is_empty() || !kind.chars().all(is_name_char) {
+            return None;
+        }
+        let mut shortcode = Shortcode {
+            name: ADMONITION_SHORTCODE.to_string(),
+            start,
+            ..Default::default()
+        };
+        shortcode.named.insert("kind".into(), kind.to_string());
+        if !title.trim().is_empty() {
+            shortcode.named.insert("title".into(), title.trim().to_string());
+        }
+        // find the closing `:::` line, skipping nested containers
+        let inner_start = (first_line_end + 1).min(markdown.len());
+        let mut depth = 0;
+        let mut line_start = inner_start;
+        while line_start < markdown.len() {
+            let end = line_end(markdown, line_start);
+            let line = markdown[line_start..end].trim_end();
+            if line == ":::" {
+                if depth == 0 {
+                    shortcode.inner = Some((inner_start, markdown[inner_start..line_start].to_string()));
+                    shortcode.end = end;
+                    return Some(shortcode);
+                }
+                depth -= 1;
+            } else if line.strip_prefix(":::").is_some_and(|rest| rest.starts_with(is_name_char)) {
+                depth += 1;
+            }
+            line_start = end + 1;
+        }
+        None
+    }
+}
+
+// offset of the end of the line containing `offset`
+fn line_end(markdown: &str, offset: usize) -> usize {
+    markdown[offset..].find('\n').map(|len| offset + len).unwrap_or(markdown.len())
+}
+
+// find the closing tag matching a shortcode, skipping nested ones with the
+// same name: (offset of the closing tag, offset past it)
+fn find_closing_tag(markdown: &str, from: usize, style: usize, name: &str) -> Option<(usize, usize)> {
+    let (open, _, close_prefix) = STYLES[style];
+    let closing_name = format!("{}{}", close_prefix, name);
+    let mut depth = 0;
+    let mut pos = from;
+    while let Some(found) = markdown[pos..].find(open) {
+        let start = pos + found;
+        pos = start + open.len();
+        let (_, contents, end) = match parse_tag(markdown, start) {
+            Some(tag) => tag,
+            None => continue,
+        };
+        let tag_name = contents.split_whitespace().next().unwrap_or_default();
+        if tag_name == closing_name {
+            if depth == 0 {
+                return Some((start, end));
+            }
+            depth -= 1;
+        } else if tag_name == name && !contents.ends_with('/') {
+            depth += 1;
+        }
+        pos = end;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_shortcode() {
+        let markdown = r#"{{< youtube dQw4w9WgXcQ title="Never \"gonna\"" start=10 >}}"#;
+        let shortcode = Shortcode::parse(markdown, 0).expect("shortcode");
+        assert_eq!(shortcode.name, "youtube");
+        assert_eq!(shortcode.args, vec!["dQw4w9WgXcQ"]);
+        assert_eq!(shortcode.named["title"], "Never \"gonna\"");
+        assert_eq!(shortcode.named["start"], "10");
+        assert_eq!(shortcode.end, markdown.len());
+
+        let markdown = "{% note %}a {% note %}b{% endnote %} c{% endnote %} after";
+        let shortcode = Shortcode::parse(markdown, 0).expect("shortcode");
+        assert_eq!(shortcode.inner, Some((10, "a {% note %}b{% endnote %} c".into())));
+        assert_eq!(&markdown[shortcode.end..], " after");
+
+        let markdown = "text\n:::tip Quick start\n:::note\nnested\n:::\nbody\n:::\nafter";
+        let shortcode = Shortcode::parse_container(markdown, 5).expect("container");
+        assert_eq!(shortcode.name, "admonition");
+        assert_eq!(shortcode.named["kind"], "tip");
+        assert_eq!(shortcode.named["title"], "Quick start");
+        assert_eq!(shortcode.inner, Some((24, ":::note\nnested\n:::\nbody\n".into())));
+        assert_eq!(&markdown[shortcode.end..], "\nafter");
+        assert!(Shortcode::parse_container(markdown, 0).is_none());
+
+        assert!(Shortcode::parse("{{< />}}", 0).is_none());
+        assert!(Shortcode::parse("{% not a shortcode", 0).is_none());
+    }
+}
@@ -0,0 +1,178 @@
+use crate::util::{escape_html, slugify};
+use pulldown_cmark::{Event, Options, Parser, Tag};
+use serde::Serialize;
+use std::collections::HashSet;
+
+// paragraph that is replaced by the table of contents
+pub const TOC_MARKER: &str = "[[toc]]";
+
+// a heading of a markdown document, with the id used to link to it
+#[derive(Debug, Clone)]
+pub struct Heading {
+    pub level: usize,
+    pub text: String,
+    pub id: String,
+}
+
+// nested table of contents, available to templates as `toc`
+#[derive(Debug, Serialize)]
+pub struct TocEntry {
+    pub level: usize,
+    pub text: String,
+    pub id: String,
+    pub children: Vec<TocEntry>,
+}
+
+// find every heading in the markdown, giving each a unique id.
+// Explicit ids (`# Title {#my-id}`) are kept, other headings get a slug
+// of their text, with `-1`, `-2`... appended to repeated titles and to ids
+// already in `used` (from elsewhere in the page), which gets the new ids.
+// `source_text` restores math and shortcodes replaced by placeholders
+pub fn collect_headings(
+    markdown: &str,
+    options: Options,
+    used: &mut HashSet<String>,
+    source_text: &dyn Fn(&str) -> String,
+) -> Vec<Heading> {
+    let mut found: Vec<(usize, String, Option<String>)> = Vec::new();
+    let mut current: Option<(usize, String, Option<String>)> = None;
+    for event in Parser::new_ext(markdown, options) {
+        match event {
+            Event::Start(Tag::Heading(level, id, _)) => {
+                current = Some((level as usize, String::new(), id.map(|id| id.to_string())));
+            }
+            Event::Text(text) | Event::Code(text) => {
+                if let Some((_, heading_text, _)) = current.as_mut() {
+                    heading_text.push_str(&text);
+                }
+            }
+            Event::End(Tag::Heading(..)) => found.extend(current.take()),
+            _ => {}
+        }
+    }
+
+    used.extend(found.iter().filter_map(|(_, _, id)| id.clone()));
+    found
+        .into_iter()
+        .map(|(level, text, id)| {
+            let text = source_text(&text);
+            let id = id.unwrap_or_else(|| {
+                let base = match slugify(&text) {
+                    slug if slug.is_empty() => "section".to_string(),
+                    slug => slug,
+                };
+                let mut id = base.clone();
+                let mut n = 1;
+                while used.contains(&id) {
+                    id = format!("{}-{}", base, n);
+                    n += 1;
+                }
+                used.insert(id.clone());
+                id
+            });
+            Heading {
+                level,
+                text: text.trim().to_string(),
+                id,
+            }
+        })
+        .collect()
+}
+
+// nest each heading under the closest preceding heading of a higher level
+pub fn build_toc(headings: &[Heading]) -> Vec<TocEntry> {
+    let mut entries = Vec::new();
+    let mut i = 0;
+    while i < headings.len() {
+        let heading = &headings[i];
+        let end = headings[i + 1..]
+            .iter()
+            .position(|next| next.level <= heading.level)
+            .map(|pos| i + 1 + pos)
+            .unwrap_or(headings.len());
+        entries.push(TocEntry {
+            level: heading.level,
+            text: heading.text.clone(),
+            id: heading.id.clone(),
+            children: build_toc(&headings[i + 1..end]),
+        });
+        i = end;
+    }
+    entries
+}
+
+fn write_toc_list(html: &mut String, entries: &[TocEntry]) {
+    html.push_str("<ul>");
+    for entry in entries {
+        html.push_str(&format!(
+            "<li><a href=\"#{}\">{}</a>",
+            escape_html(&entry.id),
+            escape_html(&entry.text)
+        ));
+        if !entry.children.is_empty() {
+            write_toc_list(html, &entry.children);
+        }
+        html.push_str("</li>");
+    }
+    html.push_str("</ul>");
+}
+
+pub fn toc_html(entries: &[TocEntry]) -> String {
+    let mut html = String::from("<nav class=\"toc\">");
+    write_toc_list(&mut html, entries);
+    html.push_str("</nav>\n");
+    html
+}
+
+// replace paragraphs containing only the `[[toc]]` marker with the given html
+pub fn replace_toc_marker<'a>(events: Vec<Event<'a>>, toc_html: &str) -> Vec<Event<'a>> {
+    let mut result = Vec::with_capacity(events.len());
+    let mut i = 0;
+    while i < events.len() {
+        if let Event::Start(Tag::Paragraph) = events[i] {
+            let mut text = String::new();
+            let mut end = None;
+            for (j, event) in events.iter().enumerate().skip(i + 1) {
+                match event {
+                    Event::Text(t) => text.push_str(t),
+                    Event::End(Tag::Paragraph) => {
+                        end = Some(j);
+                        break;
+                    }
+                    _ => break,
+                }
+            }
+            if let Some(end) = end {
+                if text.trim() == TOC_MARKER {
+                    result.push(Event::Html(toc_html.to_string().into()));
+                    i = end + 1;
+                    continue;
+                }
+            }
+        }
+        result.push(events[i].clone());
+        i += 1;
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_heading_ids_are_unique() {
+        let headings = collect_headings(
+            "# Intro\n## Setup\n## Setup\n# Setup\n",
+            Options::empty(),
+            &mut HashSet::new(),
+            &|text| text.to_string(),
+        );
+        let ids: Vec<&str> = headings.iter().map(|h| h.id.as_str()).collect();
+        assert_eq!(ids, vec!["intro", "setup", "setup-1", "setup-2"]);
+
+        let toc = build_toc(&headings);
+        assert_eq!(toc.len(), 2);
+        assert_eq!(toc[0].children.len(), 2);
+    }
+}
@@ -1,8 +1,9 @@
 use pulldown_cmark::CowStr;
-use std::path::Path;
+use std::borrow::Cow;
+use std::path::{Component, Path, PathBuf};
 
 pub fn is_audio_file(url: &CowStr) -> bool {
-    let audio_format = ["mp3", "mp4", "m4a", "wav", "ogg"];
+    let audio_format = ["mp3", "m4a", "wav", "ogg"];
     let path = Path::new(url.as_ref());
     if let Some(ext_osstr) = path.extension() {
         let extension = ext_osstr.to_string_lossy().to_lowercase();
@@ -13,6 +14,12 @@ pub fn is_audio_file(url: &CowStr) -> bool {
     false
 }
 
+pub fn is_video_file(url: &CowStr) -> bool {
+    let video_format = ["mp4", "webm", "mov"];
+    let extension = get_ext(url);
+    video_format.contains(&extension.as_str())
+}
+
 // return the extension of an url as a string
 pub fn get_ext(url: &CowStr) -> String {
     let path = Path::new(url.as_ref());
@@ -28,6 +35,9 @@ pub fn get_mimetype(ext: &str) -> String {
     match ext {
         "mp3" => "audio/mpeg",
         "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        "mov" => "video/quicktime",
+        "vtt" => "text/vtt",
         "m4a" => "audio/mp4",
         "wav" => "audio/wav",
         "ogg" => "audio/ogg",
@@ -53,3 +63,134 @@ pub fn get_mimetype(ext: &str) -> String {
         _ => "application/octet-stream",
     }.to_string()
 }
+
+// true for urls with a scheme (https:, mailto:, data: ...) or protocol-relative urls
+pub fn is_external_url(url: &str) -> bool {
+    if url.starts_with("//") {
+        return true;
+    }
+    match url.find(':') {
+        Some(colon) => {
+            let scheme = &url[..colon];
+            !scheme.is_empty()
+                && !scheme.contains('/')
+                && scheme.chars().all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+        }
+        None => false,
+    }
+}
+
+// decode %XX escapes in a url path, like `my%20notes.md`
+pub fn percent_decode(url: &str) -> Cow<'_, str> {
+    if !url.contains('%') {
+        return Cow::Borrowed(url);
+    }
+    let bytes = url.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|hex| std::str::from_utf8(hex).ok());
+        match (bytes[i], hex.and_then(|hex| u8::from_str_radix(hex, 16).ok())) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    Cow::Owned(String::from_utf8_lossy(&decoded).into_owned())
+}
+
+// escape `&`, `<`, `>` and `"` for use in html text and attribute values
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::new();
+    pulldown_cmark::escape::escape_html(&mut escaped, text).expect("write to String");
+    escaped
+}
+
+// lowercase text with runs of anything other than letters and digits
+// replaced by a single `-`, for use in urls and ids
+pub fn slugify(text: &str) -> String {
+    let mut slug = String::new();
+    for c in text.chars().flat_map(|c| c.to_lowercase()) {
+        if c.is_alphanumeric() {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.trim_end_matches('-').to_string()
+}
+
+// resolve `.` and `..` without touching the file system
+pub fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !normalized.pop() {
+                    normalized.push("..");
+                }
+            }
+            _ => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+// relative link from the page at site url `from` to site url `to`,
+// where urls have no leading `/` and directory urls end with `/`
+pub fn relative_url(from: &str, to: &str) -> String {
+    let mut from_dirs: Vec<&str> = from.split('/').collect();
+    from_dirs.pop(); // file name, or empty string for a directory
+    let to_segments: Vec<&str> = to.split('/').collect();
+    let common = from_dirs
+        .iter()
+        .zip(&to_segments[..to_segments.len() - 1])
+        .take_while(|(a, b)| a == b)
+        .count();
+    let relative = format!(
+        "{}{}",
+        "../".repeat(from_dirs.len() - common),
+        to_segments[common..].join("/")
+    );
+    if relative.is_empty() {
+        "./".to_string()
+    } else {
+        relative
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slugify() {
+        assert_eq!(slugify("Hello, World!"), "hello-world");
+        assert_eq!(slugify("  2. Getting started "), "2-getting-started");
+    }
+
+    #[test]
+    fn test_is_external_url() {
+        assert!(is_external_url("https://example.com/a.md"));
+        assert!(is_external_url("mailto:someone@example.com"));
+        assert!(is_external_url("//cdn.example.com/x.js"));
+        assert!(!is_external_url("notes/one.md"));
+        assert!(!is_external_url("notes/one.md?x=a:b"));
+        assert_eq!(percent_decode("my%20notes.md"), "my notes.md");
+    }
+
+    #[test]
+    fn test_relative_url() {
+        assert_eq!(relative_url("index.html", "notes/one.html"), "notes/one.html");
+        assert_eq!(relative_url("notes/one.html", "notes/two.html"), "two.html");
+        assert_eq!(relative_url("2022/10/hello/", "notes/one.html"), "../../../notes/one.html");
+        assert_eq!(relative_url("notes/one.html", ""), "../");
+        assert_eq!(relative_url("index.html", ""), "./");
+    }
+}
@@ -1,7 +1,15 @@
 use crate::document::Document;
+use crate::highlight::{self, Highlighter};
+use crate::icons;
+use crate::images::{self, ImageCache, ResponsiveImage};
+use crate::redirect::Redirect;
+use crate::search;
+use crate::util::{get_mimetype, normalize_path};
+use crate::Config;
 use anyhow::Context;
 use handlebars::Handlebars;
 use rust_embed::RustEmbed;
+use std::collections::HashMap;
 use std::fs;
 use std::io::Write;
 use std::path::{Path, PathBuf};
@@ -13,7 +21,22 @@ pub struct Web<'a> {
     pub out_path: PathBuf,
     pub template_dir_path: PathBuf,
     doc_list: Vec<Document>,
+    // normalized source path -> position in doc_list
+    doc_index: HashMap<PathBuf, usize>,
+    // drafts and future pages left out, with the reason, by normalized source path
+    skipped: HashMap<PathBuf, String>,
+    // pages generated from front matter `aliases`
+    redirects: Vec<Redirect>,
     pub template_registry: Handlebars<'a>,
+    pub config: Config,
+    pub(crate) highlighter: Option<Highlighter>,
+    // rendering EPUB chapters, which must be well-formed XHTML
+    pub(crate) epub: bool,
+    // resized versions of images, by url
+    pub(crate) images: HashMap<String, ResponsiveImage>,
+    pub(crate) image_cache: ImageCache,
+    // documents that failed with `keep_going`, reported once all are done
+    errors: Vec<anyhow::Error>,
 }
 
 #[derive(RustEmbed)]
@@ -30,23 +53,104 @@ fn is_hidden(entry: &DirEntry) -> bool {
         .unwrap_or(false)
 }
 
-fn new_doc_list<P: AsRef<Path>>(path_ref: P) -> anyhow::Result<Vec<Document>> {
+// documents to publish; skipped ones (drafts and future dates) go in
+// `skipped` with the reason, by normalized source path. With `keep_going`,
+// those that can't be read are left out, with their errors added to `errors`
+fn new_doc_list<P: AsRef<Path>>(
+    path_ref: P,
+    config: &Config,
+    skipped: &mut HashMap<PathBuf, String>,
+    errors: &mut Vec<anyhow::Error>,
+) -> anyhow::Result<Vec<Document>> {
     let mut vec: Vec<Document> = Vec::new();
     let root = path_ref.as_ref().to_path_buf();
 
-    let walker = WalkDir::new(root).follow_links(true).into_iter();
+    let walker = WalkDir::new(&root).follow_links(true).into_iter();
     for entry_result in walker.filter_entry(|e| !is_hidden(e)) {
         let entry = entry_result?;
         let path = entry.path();
         if fs::metadata(path)?.is_file() {
-            let doc = Document::new(path)
-                .with_context(|| format!("Failed to read {}", path.display()))?;
-            vec.push(doc);
+            let doc = Document::new(path, &root, config)
+                .and_then(|doc| Ok((doc.skip_reason(config)?, doc)))
+                .map_err(|e| file_error(path, e));
+            match doc {
+                Ok((Some(reason), _)) => {
+                    println!("skipping {}: {}", path.display(), reason);
+                    skipped.insert(normalize_path(path), reason);
+                }
+                Ok((None, doc)) => vec.push(doc),
+                Err(e) if config.keep_going => errors.push(e),
+                Err(e) => return Err(e),
+            }
         }
     }
     Ok(vec)
 }
 
+// an error from a file, naming the file unless the message already does
+// (like "notes/a.md:3:1: invalid front matter: ...")
+fn file_error(path: &Path, e: anyhow::Error) -> anyhow::Error {
+    let path = path.display().to_string();
+    if format!("{:#}", e).contains(&path) {
+        e
+    } else {
+        e.context(path)
+    }
+}
+
+// all the errors from documents that failed, as one
+fn aggregate_errors(errors: Vec<anyhow::Error>) -> anyhow::Result<()> {
+    if errors.is_empty() {
+        return Ok(());
+    }
+    let messages: Vec<String> = errors.iter().map(|e| format!("  {:#}", e)).collect();
+    anyhow::bail!("{} file(s) could not be processed:\n{}", errors.len(), messages.join("\n"))
+}
+
+// fail if two documents would be written to the same output file, or an
+// alias or resized image would replace a generated page or another file
+fn check_outpath_collisions(doc_list: &[Document], redirects: &[Redirect], config: &Config) -> anyhow::Result<()> {
+    let mut seen: HashMap<&Path, &Path> = HashMap::new();
+    for doc in doc_list {
+        if let Some(other) = seen.insert(&doc.rel_outpath, &doc.source_path) {
+            anyhow::bail!(
+                "{} and {} would both be written to {}",
+                other.display(),
+                doc.source_path.display(),
+                doc.rel_outpath.display()
+            );
+        }
+    }
+    for redirect in redirects {
+        if let Some(other) = seen.insert(&redirect.rel_outpath, &redirect.source_path) {
+            anyhow::bail!(
+                "alias {} in {} collides with output from {}",
+                redirect.url,
+                redirect.source_path.display(),
+                other.display()
+            );
+        }
+    }
+    if config.image_widths.is_empty() && !config.image_webp {
+        return Ok(());
+    }
+    let images = doc_list.iter().filter(|doc| !doc.is_markdown() && images::is_resizable_image(&doc.source_path));
+    let variants: Vec<(PathBuf, &Path)> = images
+        .flat_map(|doc| images::variant_outpaths(doc, config).into_iter().map(|path| (path, doc.source_path.as_path())))
+        .collect();
+    for (path, source_path) in &variants {
+        if let Some(other) = seen.insert(path, source_path) {
+            anyhow::bail!(
+                "resized image {} from {} would replace output from {}",
+                path.display(),
+                source_path.display(),
+                other.display()
+            );
+        }
+    }
+    Ok(())
+}
+
 impl Web<'_> {
     fn create_all_parent_dir(path: &Path) -> std::io::Result<()> {
         let dir = path.parent().unwrap();
@@ -74,6 +178,37 @@ impl Web<'_> {
         Ok(())
     }
 
+    // template variables are escaped, so a `default.hbs` from before that
+    // needs `{{{ body }}}` to output the page's html
+    fn check_raw_body<P: AsRef<Path>>(templatedir_path: P) -> anyhow::Result<()> {
+        let path = templatedir_path.as_ref().join("default.hbs");
+        if !path.exists() {
+            return Ok(());
+        }
+        let template: String = fs::read_to_string(&path)?.split_whitespace().collect();
+        if template.match_indices("{{body}}").any(|(i, _)| !template[..i].ends_with('{')) {
+            println!(
+                "warning: {} outputs {{{{ body }}}} escaped; use {{{{{{ body }}}}}} for the page's html, \
+                 or pass --unescaped-templates to render templates as before",
+                path.display()
+            );
+        }
+        Ok(())
+    }
+
+    // the embedded `partials/` templates, which the templates directory can
+    // override, so that older template directories still have them
+    fn register_default_partials(handlebars: &mut Handlebars) -> anyhow::Result<()> {
+        for relative_path_str in Asset::iter() {
+            if let Some(name) = relative_path_str.strip_prefix("partials/").and_then(|name| name.strip_suffix(".hbs")) {
+                let template = Asset::get(&relative_path_str).expect("embedded template");
+                let template = std::str::from_utf8(template.data.as_ref())?;
+                handlebars.register_template_string(&format!("partials/{}", name), template)?;
+            }
+        }
+        Ok(())
+    }
+
     // copy files recursively from `source_dir` to `dest_dir`
     // omitting files with extension `omit_ext` (.gitignore syntax from globwalk crate)
     fn copy_files<P: AsRef<Path>>(source_dir: P, dest_dir: P, omit_ext: &str) -> anyhow::Result<()> {
@@ -88,12 +223,14 @@ impl Web<'_> {
                     .expect("strip prefix match");
 
                 let dest_path = PathBuf::from(dest_dir.as_ref()).join(rel_path);
-                if dir_entry.path().is_dir() {
-                    info!("  dir: {:?}", dest_path);
-                    fs::create_dir_all(&dest_path)?;
-                } else {
+                // directories are created for the files copied into them, so
+                // template-only ones (like shortcodes/) don't appear in the output
+                if !dir_entry.path().is_dir() {
                     // copy file
                     info!("  file: {:?}", dir_entry.path());
+                    if let Some(parent) = dest_path.parent() {
+                        fs::create_dir_all(parent)?;
+                    }
 
                     match fs::copy(dir_entry.path(), &dest_path) {
                         Ok(bytes) => info!(
@@ -129,7 +266,7 @@ impl Web<'_> {
     }
 
     // creates required folders (but does not delete any old files)
-    pub fn new<P: AsRef<Path>>(in_path: P, out_path: P, templatedir_path: P) -> anyhow::Result<Self> {
+    pub fn new<P: AsRef<Path>>(in_path: P, out_path: P, templatedir_path: P, config: Config) -> anyhow::Result<Self> {
 
        fs::create_dir_all(&in_path)?;
         // create templates directory and fill with default templates if needed
@@ -138,33 +275,87 @@ impl Web<'_> {
             Self::inflate_default_templates(&templatedir_path)?;
         }
 
+        let mut errors = Vec::new();
+        let mut skipped = HashMap::new();
+        let doc_list = new_doc_list(&in_path, &config, &mut skipped, &mut errors)?;
+        let mut redirects = Vec::new();
+        for doc in &doc_list {
+            match doc.aliases(in_path.as_ref()).map_err(|e| file_error(&doc.source_path, e)) {
+                Ok(aliases) => redirects.extend(aliases),
+                Err(e) if config.keep_going => errors.push(e),
+                Err(e) => return Err(e),
+            }
+        }
+        check_outpath_collisions(&doc_list, &redirects, &config)?;
+        let doc_index = doc_list
+            .iter()
+            .enumerate()
+            .map(|(i, doc)| (normalize_path(&doc.source_path), i))
+            .collect();
+
+        let highlighter = Highlighter::new(
+            config.highlight_mode,
+            config.highlight_theme.as_deref().unwrap_or(highlight::DEFAULT_THEME),
+        )?;
+
         let mut handlebars = Handlebars::new();
+        handlebars.register_helper(icons::ICON_HELPER, Box::new(icons::icon_helper));
+        Self::register_default_partials(&mut handlebars)?;
         handlebars.register_templates_directory(".hbs", &templatedir_path)?;
-        handlebars.register_escape_fn(handlebars::no_escape);
+        if config.unescaped_templates {
+            handlebars.register_escape_fn(handlebars::no_escape);
+        } else {
+            Self::check_raw_body(&templatedir_path)?;
+        }
+        let image_cache = ImageCache::new(in_path.as_ref(), config.cache_dir.as_deref());
         Ok(Web {
             in_path: in_path.as_ref().to_path_buf(),
             out_path: out_path.as_ref().to_path_buf(),
             template_dir_path: templatedir_path.as_ref().to_path_buf(),
-            doc_list: new_doc_list(in_path)?,
+            doc_list,
+            doc_index,
+            skipped,
+            redirects,
             template_registry: handlebars,
+            config,
+            highlighter,
+            epub: false,
+            images: HashMap::new(),
+            image_cache,
+            errors,
         })
     }
 
     // given a `source_path` return corresponding output path
     fn outpath(&self, doc: &Document) -> std::io::Result<PathBuf> {
-       let rel_path = doc
-            .source_path
-            .strip_prefix(&self.in_path)
-            .expect("strip prefix match");
-        Ok(self.out_path.join(rel_path))
+        Ok(self.out_path.join(&doc.rel_outpath))
+    }
+
+    // find the document for a (normalized) source path
+    pub(crate) fn find_doc(&self, source_path: &Path) -> Option<&Document> {
+        self.doc_index.get(source_path).map(|i| &self.doc_list[*i])
     }
 
-    fn make_book_internal(&self, author: &str, title: &str) -> anyhow::Result<()> {
+    // why a (normalized) source path was left out of the site, if it was
+    pub(crate) fn skip_reason(&self, source_path: &Path) -> Option<&str> {
+        self.skipped.get(source_path).map(|reason| reason.as_str())
+    }
+
+    // documents directly in a (normalized) source directory, sorted by path
+    pub(crate) fn docs_in_dir(&self, dir: &Path) -> Vec<&Document> {
+        let mut docs: Vec<&Document> = self
+            .doc_list
+            .iter()
+            .filter(|doc| doc.source_path.parent().map(normalize_path).as_deref() == Some(dir))
+            .collect();
+        docs.sort_by(|a, b| a.source_path.cmp(&b.source_path));
+        docs
+    }
+
+    // `errors` collects the documents that failed with `keep_going`
+    fn make_book_internal(&self, author: &str, title: &str, errors: &mut Vec<anyhow::Error>) -> anyhow::Result<()> {
         use epub_builder::EpubBuilder;
-        use epub_builder::EpubContent;
-        use epub_builder::ReferenceType;
         use epub_builder::ZipLibrary;
-        use std::fs::File;
         use anyhow::anyhow;
 
         let writer = std::fs::File::create("book.epub")?;
@@ -177,61 +368,16 @@ impl Web<'_> {
         let mut chapter_number = 1;
 
         for doc in &self.doc_list {
-            let file_stem = doc.file_stem()?;
-
-            match file_stem {
-                "cover" | "_cover" =>  {
-                    println!("cover: {}", doc.source_path.display());
-                    let default_extension = "png";
-                    let extension = match doc.source_path.file_stem() {
-                        Some(os_str) => {
-                            match os_str.to_str() {
-                                Some(str) => str,
-                                None => {
-                                    println!("can't convert file extension {:?} to str", os_str);
-                                    default_extension
-                                },
-                            }
-                        },
-                        None => {
-                            println!("no file extension for cover image, assuming png");
-                            default_extension
-                        },
-                    };
-                    epub.add_cover_image(&doc.source_path,
-                                File::open(&doc.source_path)?,
-                                format!("image/{}", extension))
-                                .map_err(|err| anyhow!("adding cover image {:#?}", err))?;
-
-                },
-                "title" | "_title" =>  {
-                    println!("title page: {}", doc.source_path.display());
-                    let file_name = doc.source_path.file_name().unwrap().to_string_lossy();
-                    epub.add_content(
-                        EpubContent::new(file_name, File::open(&doc.source_path)?)
-                            .title("Title Page")
-                            .reftype(ReferenceType::TitlePage),
-                        )
-                        .map_err(|err| anyhow!("adding title page to epub {:#?}", err))?;
-                },
-                _ => {
-                    let default_zip_path = format!("chapter{}.xhtml", chapter_number);
-                    let chapter_title = format!("Chapter {}", chapter_number);  // TODO: get from YAML front matter
-                    let zip_path = match doc.source_path.file_stem() {
-                        Some(os_str) => format!("{}.xhtml", os_str.to_string_lossy()),
-                        None => default_zip_path,
-                    };
-                    println!("adding {}\tas {},\ttitle: {}", doc.source_path.display(), zip_path, chapter_title);
-                    epub.add_content(
-                        EpubContent::new(zip_path, File::open(&doc.source_path)?)
-                            .title(chapter_title)
-                            .reftype(ReferenceType::Text),
-                    )
-                    .map_err(|err| anyhow!("adding content to epub {:#?}", err))?;
-                    chapter_number = chapter_number +1;
-
-                }
-            } // match file_stem
+            match self.add_to_book(&mut epub, doc, &mut chapter_number).map_err(|e| file_error(&doc.source_path, e)) {
+                Ok(()) => {}
+                Err(e) if self.config.keep_going => errors.push(e),
+                Err(e) => return Err(e),
+            }
+        }
+        // chapters link it like web pages do, with `--highlight css`
+        if let Some(css) = self.highlighter.as_ref().and_then(|h| h.css()) {
+            epub.add_resource(highlight::HIGHLIGHT_CSS_FILE, css.as_bytes(), "text/css")
+                .map_err(|err| anyhow!("adding highlight css to epub {:#?}", err))?;
         }
         epub.generate(writer)
         .map_err(|err| anyhow!("generating epub {:#?}", err))?;
@@ -239,6 +385,93 @@ impl Web<'_> {
         Ok(())
     }
 
+    // add one document to the book, as the cover, title page, a resource or a chapter
+    fn add_to_book(
+        &self,
+        epub: &mut epub_builder::EpubBuilder<epub_builder::ZipLibrary>,
+        doc: &Document,
+        chapter_number: &mut usize,
+    ) -> anyhow::Result<()> {
+        use epub_builder::EpubContent;
+        use epub_builder::ReferenceType;
+        use anyhow::anyhow;
+
+        let file_stem = doc.file_stem()?;
+
+        match file_stem {
+            "cover" | "_cover" =>  {
+                println!("cover: {}", doc.source_path.display());
+                let default_extension = "png";
+                let extension = match doc.source_path.file_stem() {
+                    Some(os_str) => {
+                        match os_str.to_str() {
+                            Some(str) => str,
+                            None => {
+                                println!("can't convert file extension {:?} to str", os_str);
+                                default_extension
+                            },
+                        }
+                    },
+                    None => {
+                        println!("no file extension for cover image, assuming png");
+                        default_extension
+                    },
+                };
+                let content = doc.epub_content("Cover", self)?;
+                epub.add_cover_image(&doc.source_path,
+                            content.as_slice(),
+                            format!("image/{}", extension))
+                            .map_err(|err| anyhow!("adding cover image {:#?}", err))?;
+
+            },
+            "title" | "_title" =>  {
+                println!("title page: {}", doc.source_path.display());
+                let file_name = if doc.is_markdown() {
+                    format!("{}.xhtml", file_stem)
+                } else {
+                    doc.source_path.file_name().unwrap().to_string_lossy().to_string()
+                };
+                let content = doc.epub_content("Title Page", self)?;
+                epub.add_content(
+                    EpubContent::new(file_name, content.as_slice())
+                        .title("Title Page")
+                        .reftype(ReferenceType::TitlePage),
+                    )
+                    .map_err(|err| anyhow!("adding title page to epub {:#?}", err))?;
+            },
+            _ if !doc.is_markdown() => {
+                // images, audio and other files referenced by chapters
+                let ext = doc.source_path.extension().unwrap_or_default().to_string_lossy().to_lowercase();
+                let content = doc.epub_content("", self)?;
+                epub.add_resource(&doc.url, content.as_slice(), get_mimetype(&ext))
+                    .map_err(|err| anyhow!("adding resource to epub {:#?}", err))?;
+                if let Some((_, url, vtt)) = doc.converted_captions(self)? {
+                    epub.add_resource(&url, vtt.as_slice(), get_mimetype("vtt"))
+                        .map_err(|err| anyhow!("adding resource to epub {:#?}", err))?;
+                }
+            },
+            _ => {
+                let default_zip_path = format!("chapter{}.xhtml", chapter_number);
+                let chapter_title = format!("Chapter {}", chapter_number);  // TODO: get from YAML front matter
+                let zip_path = match doc.source_path.file_stem() {
+                    Some(os_str) => format!("{}.xhtml", os_str.to_string_lossy()),
+                    None => default_zip_path,
+                };
+                println!("adding {}\tas {},\ttitle: {}", doc.source_path.display(), zip_path, chapter_title);
+                let content = doc.epub_content(&chapter_title, self)?;
+                epub.add_content(
+                    EpubContent::new(zip_path, content.as_slice())
+                        .title(chapter_title)
+                        .reftype(ReferenceType::Text),
+                )
+                .map_err(|err| anyhow!("adding content to epub {:#?}", err))?;
+                *chapter_number += 1;
+
+            }
+        } // match file_stem
+        Ok(())
+    }
+
     // if folder exists, delete it & all contents and create new
     fn clean_folder<P: AsRef<Path>>(path: P) -> std::io::Result<()> {
         if Path::new(path.as_ref()).exists() {
@@ -262,21 +495,48 @@ impl Web<'_> {
     pub fn gen_book(&mut self) -> anyhow::Result<usize> {
         self.clean_and_setup_directories()?;
         info!("generating ePub for {} files", self.doc_list.len());
+        self.epub = true;
 
-        match self.make_book_internal("Author Name", "My Book") {
-            Err(e) => anyhow::bail!("Problem creating ebook: {:#?}", e),
-            Ok(_) => Ok(self.doc_list.len())
-        }
+        let mut errors = std::mem::take(&mut self.errors);
+        self.make_book_internal("Author Name", "My Book", &mut errors).context("Problem creating ebook")?;
+        aggregate_errors(errors)?;
+        Ok(self.doc_list.len())
     }
 
     pub fn gen_website(&mut self) -> anyhow::Result<usize> {
         self.clean_and_setup_directories()?;
         info!("generating html for {} files", self.doc_list.len());
+        let resizing = !self.config.image_widths.is_empty() || self.config.image_webp;
+        if resizing {
+            self.images = images::process_images(&self.doc_list, &self.config, &self.out_path, &self.image_cache)?;
+        }
+        let mut search_entries = Vec::new();
+        let mut errors = std::mem::take(&mut self.errors);
         for doc in &self.doc_list {
             let outpath = self.outpath(doc)?;
             Self::create_all_parent_dir(&outpath)?;
-            doc.webgen(&self)?;
+            match doc.webgen(self).map_err(|e| file_error(&doc.source_path, e)) {
+                Ok(Some(entry)) => search_entries.push(entry),
+                Ok(None) => {}
+                Err(e) if self.config.keep_going => errors.push(e),
+                Err(e) => return Err(e),
+            }
+        }
+        for redirect in &self.redirects {
+            redirect.write(&self.out_path)?;
+        }
+        if let Some(css) = self.highlighter.as_ref().and_then(|h| h.css()) {
+            let css_path = self.out_path.join(highlight::HIGHLIGHT_CSS_FILE);
+            Self::create_all_parent_dir(&css_path)?;
+            fs::write(&css_path, css)?;
         }
+        search::write_index(&self.out_path, &search_entries, self.config.search_inverted_index)?;
+        // only a build that resized every image knows which cached files are
+        // still needed, and pages that failed may need theirs next time
+        if resizing && errors.is_empty() {
+            self.image_cache.prune()?;
+        }
+        aggregate_errors(errors)?;
         Ok(self.doc_list.len())
     }
 
@@ -288,8 +548,191 @@ mod tests {
 
     #[test]
     fn test_new_web() {
-        let web = Web::new("markdown", "_website").expect("new web");
+        let web = Web::new("markdown", "_website", "templates", Config::default()).expect("new web");
         assert_eq!(web.in_path, Path::new("markdown"));
         assert_eq!(web.out_path, Path::new("_website"));
     }
+
+    // a site in the temp directory with the given files (in `markdown/` and
+    // `templates/`), generated with `config`; returns its directory and the result
+    fn gen_test_site(name: &str, files: &[(&str, &str)], config: Config) -> (PathBuf, anyhow::Result<usize>) {
+        let dir = std::env::temp_dir().join("webgenr-tests").join(name);
+        if dir.exists() {
+            fs::remove_dir_all(&dir).expect("remove old test site");
+        }
+        for (path, text) in files {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().expect("parent")).expect("create dir");
+            fs::write(&path, text).expect("write file");
+        }
+        let result = Web::new(dir.join("markdown"), dir.join("_website"), dir.join("templates"), config)
+            .and_then(|mut web| web.gen_website());
+        (dir, result)
+    }
+
+    #[test]
+    fn test_outpath_collisions() {
+        let files = [
+            ("markdown/page.md", "# Page\n"),
+            ("markdown/other.md", "---\naliases: [/page.html]\n---\n# Other\n"),
+        ];
+        let (_, result) = gen_test_site("alias-collision", &files, Config::default());
+        let e = result.expect_err("alias replaces a page");
+        assert!(format!("{}", e).contains("alias page.html in"), "{}", e);
+
+        let files = [
+            ("markdown/a.md", "---\npermalink: /same/\n---\n# A\n"),
+            ("markdown/b.md", "---\npermalink: /same/\n---\n# B\n"),
+        ];
+        let (_, result) = gen_test_site("permalink-collision", &files, Config::default());
+        let e = result.expect_err("two pages at one permalink");
+        assert!(format!("{}", e).contains("would both be written to same/index.html"), "{}", e);
+    }
+
+    #[test]
+    fn test_resized_images() {
+        let dir = std::env::temp_dir().join("webgenr-tests").join("resized-images");
+        if dir.exists() {
+            fs::remove_dir_all(&dir).expect("remove old test site");
+        }
+        let markdown = dir.join("markdown");
+        fs::create_dir_all(&markdown).expect("create dir");
+        image::RgbImage::new(800, 10).save(markdown.join("photo.png")).expect("write image");
+        fs::write(markdown.join("photo-480w.png"), "not a variant").expect("write file");
+        let config = Config {
+            image_widths: vec![480],
+            cache_dir: Some(dir.join("cache")),
+            ..Default::default()
+        };
+        let new_web = |config: &Config| Web::new(markdown.clone(), dir.join("_website"), dir.join("templates"), config.clone());
+        let e = new_web(&config).err().expect("variant collides with a site file");
+        assert!(format!("{}", e).contains("resized image photo-480w.png"), "{}", e);
+
+        fs::remove_file(markdown.join("photo-480w.png")).expect("remove file");
+        new_web(&config).and_then(|mut web| web.gen_website()).expect("generate");
+        assert!(dir.join("_website").join("photo-480w.png").exists());
+        let cached = || fs::read_dir(dir.join("cache").join("images")).expect("cache dir").count();
+        assert_eq!(cached(), 1);
+        // a build that doesn't resize images leaves the cache alone
+        new_web(&Config::default()).and_then(|mut web| web.gen_website()).expect("generate");
+        assert_eq!(cached(), 1);
+    }
+
+    #[test]
+    fn test_search_text_before_highlighting() {
+        let markdown = "---\nmarkdown_extensions:\n  math: true\n---\n# Notes\n\n\
+                        ```rust\nfn main() {}\n```\n\nInline $x^2$ math.\n";
+        let config = Config {
+            highlight_mode: highlight::HighlightMode::Inline,
+            ..Default::default()
+        };
+        let (dir, result) = gen_test_site("search-code", &[("markdown/notes.md", markdown)], config);
+        result.expect("generate");
+        let index = fs::read_to_string(dir.join("_website").join(search::SEARCH_INDEX_FILE)).expect("index");
+        let entries: serde_json::Value = serde_json::from_str(&index).expect("json");
+        assert_eq!(entries[0]["body"], "Notes fn main() {} Inline x^2 math.");
+    }
+
+    #[test]
+    fn test_search_text_in_admonitions() {
+        let markdown = ":::tip Quick start\nRun `webgenr` first.\n:::\n\n> [!WARNING]\n> Mind the gap.\n\n\
+                        {{< figure src=\"cat.jpg\" >}}\nA *sleepy* cat.\n{{< /figure >}}\n";
+        let (dir, result) = gen_test_site("search-admonitions", &[("markdown/notes.md", markdown)], Config::default());
+        result.expect("generate");
+        let index = fs::read_to_string(dir.join("_website").join(search::SEARCH_INDEX_FILE)).expect("index");
+        let entries: serde_json::Value = serde_json::from_str(&index).expect("json");
+        assert_eq!(entries[0]["body"], "Run webgenr first. Mind the gap. A sleepy cat.");
+    }
+
+    #[test]
+    fn test_default_image_partial() {
+        let files = [
+            ("markdown/notes.md", "![A cat](cat.png)\n"),
+            ("templates/default.hbs", "{{{ body }}}"),
+        ];
+        let (dir, result) = gen_test_site("partial-default", &files, Config::default());
+        result.expect("generate");
+        let html = fs::read_to_string(dir.join("_website").join("notes.html")).expect("html");
+        assert_eq!(html, "<p><img src=\"cat.png\" alt=\"A cat\" /></p>\n");
+    }
+
+    #[test]
+    fn test_theme_image_partial() {
+        let files = [
+            ("markdown/notes.md", "![A cat](cat.png)\n"),
+            ("templates/default.hbs", "{{{ body }}}"),
+            ("templates/partials/image.hbs", "<img class=\"framed\" src=\"{{ url }}\" alt=\"{{ alt }}\">"),
+        ];
+        let (dir, result) = gen_test_site("partial-theme", &files, Config::default());
+        result.expect("generate");
+        let html = fs::read_to_string(dir.join("_website").join("notes.html")).expect("html");
+        assert_eq!(html, "<p><img class=\"framed\" src=\"cat.png\" alt=\"A cat\"></p>\n");
+    }
+
+    #[test]
+    fn test_title_escaped_by_default() {
+        let files = [
+            ("markdown/notes.md", "---\ntitle: <script>alert(1)</script>\n---\nHi\n"),
+            ("templates/default.hbs", "<title>{{ title }}</title>"),
+        ];
+        let (dir, result) = gen_test_site("escape-default", &files, Config::default());
+        result.expect("generate");
+        let html = fs::read_to_string(dir.join("_website").join("notes.html")).expect("html");
+        assert_eq!(html, "<title>&lt;script&gt;alert(1)&lt;/script&gt;</title>");
+    }
+
+    #[test]
+    fn test_title_unescaped_templates() {
+        let files = [
+            ("markdown/notes.md", "---\ntitle: <script>alert(1)</script>\n---\nHi\n"),
+            ("templates/default.hbs", "<title>{{ title }}</title>"),
+        ];
+        let config = Config {
+            unescaped_templates: true,
+            ..Default::default()
+        };
+        let (dir, result) = gen_test_site("escape-off", &files, config);
+        result.expect("generate");
+        let html = fs::read_to_string(dir.join("_website").join("notes.html")).expect("html");
+        assert_eq!(html, "<title><script>alert(1)</script></title>");
+    }
+
+    #[test]
+    fn test_shortcode_args_escaped() {
+        let files = [
+            ("markdown/notes.md", "{{< quote text=\"<script>alert(1)</script>\" >}}\n"),
+            ("templates/default.hbs", "{{ body }}"),
+            ("templates/shortcodes/quote.hbs", "<q>{{ text }}</q>"),
+        ];
+        let config = Config {
+            unescaped_templates: true,
+            sanitize: true,
+            ..Default::default()
+        };
+        let (dir, result) = gen_test_site("shortcode-escaped", &files, config);
+        result.expect("generate");
+        let html = fs::read_to_string(dir.join("_website").join("notes.html")).expect("html");
+        assert_eq!(html, "<q>&lt;script&gt;alert(1)&lt;/script&gt;</q>\n");
+    }
+
+    #[test]
+    fn test_shortcode_urls_sanitized() {
+        let files = [
+            (
+                "markdown/notes.md",
+                "{{< figure src=\"javascript:alert(1)\" >}}\n\n{{< figure src=\"cat.jpg\" >}}\n",
+            ),
+            ("templates/default.hbs", "{{ body }}"),
+            ("templates/shortcodes/figure.hbs", "<img src=\"{{ src }}\">"),
+        ];
+        let config = Config {
+            unescaped_templates: true,
+            sanitize: true,
+            ..Default::default()
+        };
+        let (dir, result) = gen_test_site("shortcode-urls", &files, config);
+        result.expect("generate");
+        let html = fs::read_to_string(dir.join("_website").join("notes.html")).expect("html");
+        assert_eq!(html, "<img src=\"\">\n<img src=\"cat.jpg\">\n");
+    }
 }
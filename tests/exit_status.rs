@@ -0,0 +1,56 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+// a site in the temp directory with the given markdown files
+fn site(name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let dir = std::env::temp_dir().join("webgenr-tests").join(name);
+    if dir.exists() {
+        fs::remove_dir_all(&dir).expect("remove old test site");
+    }
+    fs::create_dir_all(dir.join("markdown")).expect("create dir");
+    for (path, text) in files {
+        fs::write(dir.join("markdown").join(path), text).expect("write markdown");
+    }
+    dir
+}
+
+fn webgenr(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_webgenr"))
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .expect("run webgenr")
+}
+
+#[test]
+fn test_exit_status() {
+    let good = ("good.md", "# Good\n");
+    let dir = site("exit-ok", &[good]);
+    assert!(webgenr(&dir, &[]).status.success());
+
+    let bad = ("bad.md", "---\ntitle: [oops\n---\n# Bad\n");
+    let dir = site("exit-bad", &[good, bad]);
+    let output = webgenr(&dir, &[]);
+    assert_eq!(output.status.code(), Some(1));
+    // the file is named once, with the line
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(stderr.matches("bad.md").count(), 1, "{}", stderr);
+    assert!(stderr.contains("markdown/bad.md:3:1: invalid front matter"), "{}", stderr);
+}
+
+#[test]
+fn test_keep_going_exit_status() {
+    let files = [("good.md", "# Good\n"), ("link.md", "[x](missing.md)\n")];
+    let dir = site("exit-keep-going", &files);
+    let output = webgenr(&dir, &["--strict-links", "--keep-going"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(dir.join("_website").join("good.html").exists());
+
+    // a chapter that fails doesn't stop the book either
+    let output = webgenr(&dir, &["--strict-links", "--keep-going", "--book"]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("1 file(s) could not be processed"), "{}", stderr);
+    assert!(dir.join("book.epub").exists());
+}